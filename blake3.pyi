@@ -1,5 +1,8 @@
 from os import PathLike
+from pathlib import Path
 import sys
+from types import TracebackType
+from typing import BinaryIO, Iterable, Iterator, Literal, Protocol, TextIO, overload
 if sys.version_info >= (3, 12):
     from collections.abc import Buffer
 else:
@@ -7,6 +10,158 @@ else:
 
 __version__: str = ...
 
+class _ReadIntoFile(Protocol):
+    def readinto(self, buffer: Buffer, /) -> int | None: ...
+
+AUTO: int
+
+def hash(data: Buffer, /, length: int = ..., *, max_threads: int = ...) -> bytes: ...
+def keyed_hash(
+    key: Buffer, data: Buffer, /, length: int = ..., *, max_threads: int = ...
+) -> bytes: ...
+def derive_key(
+    context: str, key_material: Buffer, /, length: int = ..., *, max_threads: int = ...
+) -> bytes: ...
+
+class VerificationError(ValueError): ...
+
+@overload
+def hash_many(
+    inputs: Iterable[Buffer],
+    /,
+    *,
+    key: Buffer | None = ...,
+    derive_key_context: str | None = ...,
+    length: int = ...,
+    max_threads: int = ...,
+    concatenate: Literal[False] = ...,
+) -> list[bytes]: ...
+@overload
+def hash_many(
+    inputs: Iterable[Buffer],
+    /,
+    *,
+    key: Buffer | None = ...,
+    derive_key_context: str | None = ...,
+    length: int = ...,
+    max_threads: int = ...,
+    concatenate: Literal[True],
+) -> bytes: ...
+@overload
+def hash_rows(
+    data: Buffer,
+    /,
+    *,
+    row_length: int | None = ...,
+    key: Buffer | None = ...,
+    derive_key_context: str | None = ...,
+    length: int = ...,
+    as_int: Literal[False] = ...,
+    max_threads: int = ...,
+) -> bytes: ...
+@overload
+def hash_rows(
+    data: Buffer,
+    /,
+    *,
+    row_length: int | None = ...,
+    key: Buffer | None = ...,
+    derive_key_context: str | None = ...,
+    length: int = ...,
+    as_int: Literal[True],
+    max_threads: int = ...,
+) -> list[int]: ...
+@overload
+def hash_file(
+    path_or_fileobj: str | PathLike[str] | _ReadIntoFile,
+    /,
+    *,
+    key: Buffer | None = ...,
+    derive_key_context: str | None = ...,
+    length: int = ...,
+    max_threads: int = ...,
+    offset: int | None = ...,
+    size: int | None = ...,
+    as_hash: Literal[False] = ...,
+) -> bytes: ...
+@overload
+def hash_file(
+    path_or_fileobj: str | PathLike[str] | _ReadIntoFile,
+    /,
+    *,
+    key: Buffer | None = ...,
+    derive_key_context: str | None = ...,
+    length: Literal[32] = ...,
+    max_threads: int = ...,
+    offset: int | None = ...,
+    size: int | None = ...,
+    as_hash: Literal[True],
+) -> Hash: ...
+@overload
+def hash_files(
+    paths: str | PathLike[str] | Iterable[str | PathLike[str]],
+    /,
+    *,
+    key: Buffer | None = ...,
+    derive_key_context: str | None = ...,
+    length: int = ...,
+    max_threads: int = ...,
+    follow_symlinks: bool = ...,
+    include: Iterable[str] | None = ...,
+    exclude: Iterable[str] | None = ...,
+    on_error: Literal["raise", "skip"] = ...,
+    stream: Literal[False] = ...,
+) -> dict[Path, bytes]: ...
+@overload
+def hash_files(
+    paths: str | PathLike[str] | Iterable[str | PathLike[str]],
+    /,
+    *,
+    key: Buffer | None = ...,
+    derive_key_context: str | None = ...,
+    length: int = ...,
+    max_threads: int = ...,
+    follow_symlinks: bool = ...,
+    include: Iterable[str] | None = ...,
+    exclude: Iterable[str] | None = ...,
+    on_error: Literal["return"],
+    stream: Literal[False] = ...,
+) -> dict[Path, bytes | OSError]: ...
+@overload
+def hash_files(
+    paths: str | PathLike[str] | Iterable[str | PathLike[str]],
+    /,
+    *,
+    key: Buffer | None = ...,
+    derive_key_context: str | None = ...,
+    length: int = ...,
+    max_threads: int = ...,
+    follow_symlinks: bool = ...,
+    include: Iterable[str] | None = ...,
+    exclude: Iterable[str] | None = ...,
+    on_error: Literal["raise", "skip", "return"] = ...,
+    stream: Literal[True],
+) -> HashFilesIterator: ...
+def checksum_lines(
+    paths: Iterable[str | PathLike[str]],
+    /,
+    *,
+    length: int = ...,
+    max_threads: int = ...,
+) -> list[str]: ...
+def check(
+    checkfile: str | PathLike[str] | BinaryIO | TextIO, /, *, max_threads: int = ...
+) -> list[CheckResult]: ...
+def set_snapshot_default(snapshot: bool | Literal["detect"], /) -> None: ...
+def get_snapshot_default() -> bool | Literal["detect"]: ...
+def compare_digest(a: Buffer, b: Buffer, /) -> bool: ...
+def encode_digest(
+    data: Buffer, encoding: str, /, *, uppercase: bool = ..., sep: str | None = ...
+) -> str: ...
+def parse_multihash(data: Buffer, /) -> bytes: ...
+def parse_cid(cid: str, /) -> bytes: ...
+def decode_digest(text: str, encoding: str, /, *, sep: str | None = ...) -> bytes: ...
+
 class blake3:
     name: str
     digest_size: int
@@ -22,10 +177,115 @@ class blake3:
         derive_key_context: str = ...,
         max_threads: int = ...,
         usedforsecurity: bool = ...,
+        order: Literal["C", "F", "A"] = ...,
+        snapshot: bool | Literal["detect"] | None = ...,
     ): ...
-    def update(self, data: Buffer, /) -> blake3: ...
-    def update_mmap(self, path: str | PathLike[str]) -> blake3: ...
+    def update(
+        self, data: Buffer, /, *, order: Literal["C", "F", "A"] = ...
+    ) -> blake3: ...
+    def update_iter(self, iterable: Iterable[Buffer], /) -> blake3: ...
+    def update_many(self, buffers: Iterable[Buffer], /) -> blake3: ...
+    def update_array(
+        self,
+        data: Buffer,
+        /,
+        *,
+        byteorder: Literal["little", "big"] = ...,
+        order: Literal["C", "F", "A"] = ...,
+    ) -> blake3: ...
+    def update_str(
+        self, s: str, /, encoding: str = ..., errors: str = ...
+    ) -> blake3: ...
+    def update_mmap(
+        self,
+        path: str | PathLike[str],
+        *,
+        offset: int | None = ...,
+        length: int | None = ...,
+    ) -> blake3: ...
+    def update_path(
+        self,
+        path: str | PathLike[str],
+        *,
+        offset: int | None = ...,
+        length: int | None = ...,
+    ) -> blake3: ...
+    def update_file(
+        self, fileobj: _ReadIntoFile, /, *, buffer_size: int | None = ...
+    ) -> blake3: ...
+    def update_fd(
+        self, fd: int, /, *, length: int | None = ..., offset: int | None = ...
+    ) -> blake3: ...
     def copy(self) -> blake3: ...
     def reset(self) -> None: ...
     def digest(self, length: int = ..., *, seek: int = ...) -> bytes: ...
     def hexdigest(self, length: int = ..., *, seek: int = ...) -> str: ...
+    def digest_into(self, buffer: Buffer, /, *, seek: int = ...) -> None: ...
+    def hexdigest_into(self, buffer: Buffer, /, *, seek: int = ...) -> None: ...
+    def encoded_digest(
+        self,
+        encoding: str,
+        length: int = ...,
+        *,
+        seek: int = ...,
+        uppercase: bool = ...,
+        sep: str | None = ...,
+    ) -> str: ...
+    def multihash(self, length: int = ..., *, seek: int = ...) -> bytes: ...
+    def cid(
+        self,
+        codec: int | str = ...,
+        base: str = ...,
+        *,
+        length: int = ...,
+        seek: int = ...,
+    ) -> str: ...
+    def verify(self, expected: Buffer, /, *, seek: int = ...) -> None: ...
+    def finalize(self) -> Hash: ...
+    def finalize_xof(self) -> OutputReader: ...
+
+class Hash:
+    def __init__(self, data: Buffer, /) -> None: ...
+    @staticmethod
+    def from_hex(hex: str, /) -> Hash: ...
+    def to_hex(self) -> str: ...
+    def __bytes__(self) -> bytes: ...
+    def __eq__(self, other: object) -> bool: ...
+    def __hash__(self) -> int: ...
+
+class OutputReader:
+    def read(self, size: int = ..., /) -> bytes: ...
+    def readinto(self, buffer: Buffer, /) -> int: ...
+    def readall(self) -> bytes: ...
+    def readline(self, size: int = ..., /) -> bytes: ...
+    def readlines(self, hint: int = ..., /) -> list[bytes]: ...
+    def seek(self, offset: int, whence: int = ..., /) -> int: ...
+    def tell(self) -> int: ...
+    def readable(self) -> bool: ...
+    def seekable(self) -> bool: ...
+    def writable(self) -> bool: ...
+    def isatty(self) -> bool: ...
+    def flush(self) -> None: ...
+    def fileno(self) -> int: ...
+    def close(self) -> None: ...
+    @property
+    def closed(self) -> bool: ...
+    def __iter__(self) -> OutputReader: ...
+    def __next__(self) -> bytes: ...
+    def __enter__(self) -> OutputReader: ...
+    def __exit__(
+        self,
+        exc_type: type[BaseException] | None,
+        exc_value: BaseException | None,
+        traceback: TracebackType | None,
+    ) -> None: ...
+
+class HashFilesIterator(Iterator[tuple[Path, bytes | OSError]]):
+    def __next__(self) -> tuple[Path, bytes | OSError]: ...
+
+class CheckResult:
+    path: str
+    status: Literal["OK", "FAILED", "MISSING", "ERROR"]
+    error: OSError | None
+    @property
+    def ok(self) -> bool: ...
@@ -0,0 +1,84 @@
+//! The checksum file format that `b3sum` writes and that `b3sum --check`
+//! reads, for `checksum_lines` and `check`. Each line is a hex hash, two
+//! spaces, and a file path. Paths containing backslashes or newlines are
+//! escaped, and their lines start with a backslash, like in `md5sum`.
+
+use std::path::Path;
+
+/// Format one line of a checksum file, including the trailing newline.
+pub(crate) fn format_line(hash: &[u8], path: &Path) -> String {
+    let mut path_string = path.to_string_lossy().into_owned();
+    // b3sum always writes forward slashes, so that checksum files work on
+    // every platform.
+    if cfg!(windows) {
+        path_string = path_string.replace('\\', "/");
+    }
+    let mut line = String::new();
+    if path_string.contains(['\\', '\n', '\r']) {
+        path_string = path_string
+            .replace('\\', "\\\\")
+            .replace('\n', "\\n")
+            .replace('\r', "\\r");
+        line.push('\\');
+    }
+    line.push_str(&hex::encode(hash));
+    line.push_str("  ");
+    line.push_str(&path_string);
+    line.push('\n');
+    line
+}
+
+/// Parse one line of a checksum file, without its trailing newline, and
+/// return the expected hash and the path. b3sum only reads 32-byte hashes,
+/// but we accept any length, to match the `--length` option.
+pub(crate) fn parse_line(line: &str) -> Result<(Vec<u8>, String), &'static str> {
+    if line.is_empty() {
+        return Err("empty line");
+    }
+    let (is_escaped, line) = match line.strip_prefix('\\') {
+        Some(rest) => (true, rest),
+        None => (false, line),
+    };
+    // Paths can contain two spaces, but hashes can't, so split at the first.
+    let Some((hash_hex, path_string)) = line.split_once("  ") else {
+        return Err("expected a hash and a path separated by two spaces");
+    };
+    // Like b3sum, only accept lowercase hex.
+    if hash_hex.is_empty() || hash_hex.bytes().any(|b| b.is_ascii_uppercase()) {
+        return Err("invalid hash");
+    }
+    let hash = hex::decode(hash_hex).map_err(|_| "invalid hash")?;
+    if path_string.is_empty() {
+        return Err("empty path");
+    }
+    let path_string = if is_escaped {
+        unescape(path_string)?
+    } else {
+        path_string.to_owned()
+    };
+    if path_string.contains('\0') {
+        return Err("null character in path");
+    }
+    // Checksum files use forward slashes, and a backslash here would be a
+    // path separator that b3sum couldn't have written.
+    if cfg!(windows) && path_string.contains('\\') {
+        return Err("backslash in path");
+    }
+    Ok((hash, path_string))
+}
+
+fn unescape(mut path: &str) -> Result<String, &'static str> {
+    let mut unescaped = String::with_capacity(path.len());
+    while let Some(i) = path.find('\\') {
+        unescaped.push_str(&path[..i]);
+        match path[i + 1..].chars().next() {
+            Some('n') => unescaped.push('\n'),
+            Some('r') => unescaped.push('\r'),
+            Some('\\') => unescaped.push('\\'),
+            _ => return Err("invalid backslash escape"),
+        }
+        path = &path[i + 2..];
+    }
+    unescaped.push_str(path);
+    Ok(unescaped)
+}
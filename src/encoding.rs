@@ -0,0 +1,270 @@
+//! Text encodings for digests, used by `encoded_digest`, `encode_digest` and
+//! `decode_digest`. Everything here works on plain Rust slices and strings,
+//! so the callers are responsible for getting bytes out of Python buffers.
+
+use pyo3::exceptions::{PyMemoryError, PyValueError};
+use pyo3::prelude::*;
+use std::sync::OnceLock;
+
+const BASE32_SYMBOLS: &str = "ABCDEFGHIJKLMNOPQRSTUVWXYZ234567";
+const CROCKFORD_SYMBOLS: &str = "0123456789ABCDEFGHJKMNPQRSTVWXYZ";
+const Z85_SYMBOLS: &[u8; 85] =
+    b"0123456789abcdefghijklmnopqrstuvwxyzABCDEFGHIJKLMNOPQRSTUVWXYZ.-:+=^!/*?&<>()[]{}@%$#";
+
+/// The most bytes we encode as base58. Base58 isn't a power-of-two base, so
+/// encoding and decoding take quadratic time, and a long input could hold the
+/// GIL for minutes.
+pub(crate) const MAX_BASE58_LEN: usize = 1024;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum Encoding {
+    /// Base16, the same as `hexdigest`.
+    Hex,
+    /// RFC 4648 base32, without padding.
+    Base32,
+    /// Crockford's base32, without check symbols. Decoding is case-insensitive,
+    /// maps I and L to 1 and O to 0, and ignores hyphens.
+    Base32Crockford,
+    /// Base58 with the Bitcoin alphabet, which is what IPFS and most other
+    /// applications use.
+    Base58,
+    /// RFC 4648 base64, with padding.
+    Base64,
+    /// RFC 4648 URL-safe base64, without padding.
+    Base64Url,
+    /// ZeroMQ's Z85. The input length must be a multiple of 4.
+    Z85,
+}
+
+impl Encoding {
+    pub(crate) const NAMES: &'static [&'static str] = &[
+        "hex",
+        "base32",
+        "base32crockford",
+        "base58",
+        "base64",
+        "base64url",
+        "z85",
+    ];
+
+    pub(crate) fn from_name(name: &str) -> PyResult<Self> {
+        Ok(match name {
+            "hex" => Self::Hex,
+            "base32" => Self::Base32,
+            "base32crockford" => Self::Base32Crockford,
+            "base58" => Self::Base58,
+            "base64" => Self::Base64,
+            "base64url" => Self::Base64Url,
+            "z85" => Self::Z85,
+            _ => {
+                let msg = format!(
+                    "unknown encoding {name:?}, expected one of: {}",
+                    Self::NAMES.join(", "),
+                );
+                return Err(PyValueError::new_err(msg));
+            }
+        })
+    }
+
+    /// Whether the `uppercase` option applies. The other encodings are
+    /// case-sensitive.
+    fn is_case_insensitive(self) -> bool {
+        matches!(self, Self::Hex | Self::Base32 | Self::Base32Crockford)
+    }
+
+    /// Check that we're willing to encode `len` bytes. Only base58 has a
+    /// limit, `MAX_BASE58_LEN`.
+    pub(crate) fn check_len(self, len: usize) -> PyResult<()> {
+        if self == Self::Base58 && len > MAX_BASE58_LEN {
+            let msg = format!("base58 supports at most {MAX_BASE58_LEN} bytes, found {len}");
+            return Err(PyValueError::new_err(msg));
+        }
+        Ok(())
+    }
+
+    /// Encode `bytes`. `uppercase` is only allowed for case-insensitive
+    /// encodings, and `sep` is only allowed for hex, where it goes between
+    /// each pair of digits like in `bytes.hex`. The output is allocated
+    /// fallibly, so a huge input raises `MemoryError`.
+    pub(crate) fn encode(
+        self,
+        bytes: &[u8],
+        uppercase: bool,
+        sep: Option<&str>,
+    ) -> PyResult<String> {
+        if uppercase && !self.is_case_insensitive() {
+            let msg = format!("{} doesn't support uppercase", self.name());
+            return Err(PyValueError::new_err(msg));
+        }
+        if sep.is_some() && self != Self::Hex {
+            let msg = format!("{} doesn't support separators", self.name());
+            return Err(PyValueError::new_err(msg));
+        }
+        self.check_len(bytes.len())?;
+        let encoded_len = match self {
+            Self::Hex => {
+                let sep_len = sep.map_or(0, str::len);
+                bytes.len().saturating_mul(2 + sep_len)
+            }
+            Self::Base32 => base32_encoding().encode_len(bytes.len()),
+            Self::Base32Crockford => crockford_encoding().encode_len(bytes.len()),
+            // This one is short, and bs58 allocates its own output.
+            Self::Base58 => 0,
+            Self::Base64 => data_encoding::BASE64.encode_len(bytes.len()),
+            Self::Base64Url => data_encoding::BASE64URL_NOPAD.encode_len(bytes.len()),
+            Self::Z85 => bytes.len() / 4 * 5,
+        };
+        let mut encoded = String::new();
+        if encoded.try_reserve_exact(encoded_len).is_err() {
+            let msg = format!("can't allocate a {encoded_len}-byte {} string", self.name());
+            return Err(PyMemoryError::new_err(msg));
+        }
+        match self {
+            Self::Hex => push_hex(bytes, sep.unwrap_or(""), uppercase, &mut encoded),
+            Self::Base32 => base32_encoding().encode_append(bytes, &mut encoded),
+            Self::Base32Crockford => crockford_encoding().encode_append(bytes, &mut encoded),
+            Self::Base58 => encoded = bs58::encode(bytes).into_string(),
+            Self::Base64 => data_encoding::BASE64.encode_append(bytes, &mut encoded),
+            Self::Base64Url => data_encoding::BASE64URL_NOPAD.encode_append(bytes, &mut encoded),
+            Self::Z85 => z85_encode(bytes, &mut encoded)?,
+        }
+        // The base32 specs below produce uppercase. Hex is already in the
+        // right case, and changing it here would change the separator too.
+        if matches!(self, Self::Base32 | Self::Base32Crockford) && !uppercase {
+            encoded.make_ascii_lowercase();
+        }
+        Ok(encoded)
+    }
+
+    /// Decode `text`. Case-insensitive encodings accept either case. If `sep`
+    /// is given (only for hex), all occurrences of it are skipped.
+    pub(crate) fn decode(self, text: &str, sep: Option<&str>) -> PyResult<Vec<u8>> {
+        let result = match self {
+            Self::Hex => match sep {
+                Some(sep) => hex::decode(text.replace(sep, "")).map_err(|e| e.to_string()),
+                None => hex::decode(text).map_err(|e| e.to_string()),
+            },
+            _ if sep.is_some() => {
+                let msg = format!("{} doesn't support separators", self.name());
+                return Err(PyValueError::new_err(msg));
+            }
+            Self::Base32 => base32_encoding()
+                .decode(text.as_bytes())
+                .map_err(|e| e.to_string()),
+            Self::Base32Crockford => crockford_encoding()
+                .decode(text.as_bytes())
+                .map_err(|e| e.to_string()),
+            // Decoding takes quadratic time too. Encoding `MAX_BASE58_LEN`
+            // bytes gives fewer than twice that many digits.
+            Self::Base58 if text.len() > MAX_BASE58_LEN * 2 => Err(format!(
+                "at most {} digits are supported",
+                MAX_BASE58_LEN * 2
+            )),
+            Self::Base58 => bs58::decode(text).into_vec().map_err(|e| e.to_string()),
+            Self::Base64 => data_encoding::BASE64
+                .decode(text.as_bytes())
+                .map_err(|e| e.to_string()),
+            Self::Base64Url => data_encoding::BASE64URL_NOPAD
+                .decode(text.as_bytes())
+                .map_err(|e| e.to_string()),
+            Self::Z85 => z85_decode(text.as_bytes()),
+        };
+        result.map_err(|e| PyValueError::new_err(format!("invalid {}: {e}", self.name())))
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            Self::Hex => "hex",
+            Self::Base32 => "base32",
+            Self::Base32Crockford => "base32crockford",
+            Self::Base58 => "base58",
+            Self::Base64 => "base64",
+            Self::Base64Url => "base64url",
+            Self::Z85 => "z85",
+        }
+    }
+}
+
+fn push_hex(bytes: &[u8], sep: &str, uppercase: bool, encoded: &mut String) {
+    let digits = if uppercase {
+        b"0123456789ABCDEF"
+    } else {
+        b"0123456789abcdef"
+    };
+    for (i, &byte) in bytes.iter().enumerate() {
+        if i > 0 {
+            encoded.push_str(sep);
+        }
+        encoded.push(digits[usize::from(byte >> 4)] as char);
+        encoded.push(digits[usize::from(byte & 0xf)] as char);
+    }
+}
+
+/// Unpadded RFC 4648 base32. This encodes uppercase and decodes either case.
+fn base32_encoding() -> &'static data_encoding::Encoding {
+    static ENCODING: OnceLock<data_encoding::Encoding> = OnceLock::new();
+    ENCODING.get_or_init(|| {
+        let mut spec = data_encoding::Specification::new();
+        spec.symbols.push_str(BASE32_SYMBOLS);
+        spec.translate.from.push_str("abcdefghijklmnopqrstuvwxyz");
+        spec.translate.to.push_str("ABCDEFGHIJKLMNOPQRSTUVWXYZ");
+        spec.encoding().unwrap()
+    })
+}
+
+/// Crockford's base32. This encodes uppercase and decodes either case, with
+/// the substitutions and hyphen skipping that Crockford specifies.
+fn crockford_encoding() -> &'static data_encoding::Encoding {
+    static ENCODING: OnceLock<data_encoding::Encoding> = OnceLock::new();
+    ENCODING.get_or_init(|| {
+        let mut spec = data_encoding::Specification::new();
+        spec.symbols.push_str(CROCKFORD_SYMBOLS);
+        spec.translate.from.push_str("abcdefghjkmnpqrstvwxyzIiLlOo");
+        spec.translate.to.push_str("ABCDEFGHJKMNPQRSTVWXYZ111100");
+        spec.ignore.push('-');
+        spec.encoding().unwrap()
+    })
+}
+
+fn z85_encode(bytes: &[u8], encoded: &mut String) -> PyResult<()> {
+    let words = bytes.chunks_exact(4);
+    if !words.remainder().is_empty() {
+        let msg = format!("z85 needs a multiple of 4 bytes, found {}", bytes.len());
+        return Err(PyValueError::new_err(msg));
+    }
+    for word in words {
+        let mut value = u32::from_be_bytes(word.try_into().unwrap());
+        let mut digits = [0u8; 5];
+        for digit in digits.iter_mut().rev() {
+            *digit = Z85_SYMBOLS[(value % 85) as usize];
+            value /= 85;
+        }
+        encoded.extend(digits.iter().map(|&d| d as char));
+    }
+    Ok(())
+}
+
+fn z85_decode(text: &[u8]) -> Result<Vec<u8>, String> {
+    let groups = text.chunks_exact(5);
+    if !groups.remainder().is_empty() {
+        return Err(format!(
+            "length must be a multiple of 5, found {}",
+            text.len()
+        ));
+    }
+    let mut decoded = Vec::with_capacity(text.len() / 5 * 4);
+    for digits in groups {
+        let mut value: u64 = 0;
+        for &digit in digits {
+            let Some(index) = Z85_SYMBOLS.iter().position(|&s| s == digit) else {
+                return Err(format!("invalid symbol {:?}", digit as char));
+            };
+            value = value * 85 + index as u64;
+        }
+        let Ok(word) = u32::try_from(value) else {
+            return Err("value out of range".into());
+        };
+        decoded.extend_from_slice(&word.to_be_bytes());
+    }
+    Ok(decoded)
+}
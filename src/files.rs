@@ -0,0 +1,551 @@
+//! File helpers: memory mapping, truncation-safe positional reads, borrowing
+//! Python file descriptors, and the directory walking and glob filtering for `hash_files`.
+
+use pyo3::exceptions::{PyOSError, PyValueError};
+use pyo3::prelude::*;
+use rayon::prelude::*;
+use std::fs::{self, File};
+use std::io;
+use std::mem::ManuallyDrop;
+use std::ops::Deref;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+/// Convert an `io::Error` into an `OSError` that includes the path, like the
+/// errors from Python's own file functions. Python picks the right subclass,
+/// e.g. `FileNotFoundError`, based on the error code.
+pub(crate) fn os_error(path: &Path, err: io::Error) -> PyErr {
+    let Some(code) = err.raw_os_error() else {
+        return err.into();
+    };
+    // Python adds the error code itself, so don't repeat it.
+    let msg = err.to_string();
+    let msg = msg
+        .strip_suffix(&format!(" (os error {code})"))
+        .unwrap_or(&msg)
+        .to_owned();
+    // On Windows, the raw code is a Windows error code, and Python derives
+    // the errno from it.
+    #[cfg(windows)]
+    return PyOSError::new_err((0, msg, path.as_os_str().to_owned(), code));
+    #[cfg(not(windows))]
+    PyOSError::new_err((code, msg, path.as_os_str().to_owned()))
+}
+
+/// Mapping small files is not worth it, and some special files that can't be
+/// mapped report a size of zero. This is the same threshold upstream
+/// `update_mmap` uses.
+const MMAP_MIN_LEN: u64 = 16 * 1024;
+
+/// Memory map a file from `offset` to the end. Return None if it's not a
+/// regular file, or if that part of it is too short to be worth mapping, and
+/// the caller should read it normally instead.
+pub(crate) fn maybe_mmap_file(file: &File, offset: u64) -> io::Result<Option<memmap2::Mmap>> {
+    let metadata = file.metadata()?;
+    if !metadata.is_file() || metadata.len().saturating_sub(offset) < MMAP_MIN_LEN {
+        return Ok(None);
+    }
+    // Safety: Like upstream `update_mmap`, we assume that the file isn't
+    // modified while it's mapped. See the caveats in the docs there.
+    let mmap = unsafe { memmap2::MmapOptions::new().offset(offset).map(file)? };
+    Ok(Some(mmap))
+}
+
+/// A range of bytes from a file, either memory mapped or, if it's short, read
+/// into memory.
+pub(crate) enum FileRange {
+    Mapped(memmap2::Mmap),
+    Read(Vec<u8>),
+}
+
+impl Deref for FileRange {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        match self {
+            FileRange::Mapped(mmap) => mmap,
+            FileRange::Read(bytes) => bytes,
+        }
+    }
+}
+
+const RANGE_NOT_REGULAR_FILE: &str = "offset and length are only supported for regular files";
+
+/// Check that a range is within a file of length `file_len`, and return the
+/// length of the range. A `length` of None means the rest of the file.
+fn checked_range_len(file_len: u64, offset: u64, length: Option<u64>) -> PyResult<u64> {
+    if offset > file_len {
+        return Err(PyValueError::new_err(format!(
+            "offset {offset} is past the end of the file ({file_len} bytes)"
+        )));
+    }
+    let range_len = length.unwrap_or(file_len - offset);
+    if range_len > file_len - offset {
+        return Err(PyValueError::new_err(format!(
+            "offset {offset} plus length {range_len} is past the end of the file ({file_len} bytes)"
+        )));
+    }
+    Ok(range_len)
+}
+
+/// Get the bytes of a regular file from `offset` to `offset + length`, or to
+/// the end if `length` is None. Unlike `maybe_mmap_file`, this only maps the
+/// requested range, and it's an error if the range goes past the end of the
+/// file.
+pub(crate) fn file_range(path: &Path, offset: u64, length: Option<u64>) -> PyResult<FileRange> {
+    let file = File::open(path).map_err(|e| os_error(path, e))?;
+    let metadata = file.metadata().map_err(|e| os_error(path, e))?;
+    if !metadata.is_file() {
+        return Err(PyValueError::new_err(RANGE_NOT_REGULAR_FILE));
+    }
+    let range_len = checked_range_len(metadata.len(), offset, length)?;
+    let Ok(range_len) = usize::try_from(range_len) else {
+        return Err(PyValueError::new_err("length doesn't fit in memory"));
+    };
+    if (range_len as u64) < MMAP_MIN_LEN {
+        let mut bytes = vec![0; range_len];
+        read_exact_at(&file, &mut bytes, offset).map_err(|e| os_error(path, e))?;
+        return Ok(FileRange::Read(bytes));
+    }
+    // Safety: Like upstream `update_mmap`, we assume that the file isn't
+    // modified while it's mapped. See the caveats in the docs there.
+    let mmap = unsafe {
+        memmap2::MmapOptions::new()
+            .offset(offset)
+            .len(range_len)
+            .map(&file)
+            .map_err(|e| os_error(path, e))?
+    };
+    Ok(FileRange::Mapped(mmap))
+}
+
+/// Read a file from `offset` to `offset + length`, or to the end, with
+/// positional reads instead of a memory map, and pass the bytes to `update`
+/// in order, at most `buffer_len` at a time. If `parallel` is true, each
+/// round reads one buffer per thread in the current rayon pool at once.
+///
+/// If another process truncates a file while it's memory mapped, touching the
+/// missing pages raises SIGBUS, which kills the whole process. Reads just come
+/// up short instead, and we report that, or any other change in the file's
+/// size while we're reading it, as an `OSError`. Files that aren't regular
+/// files, like pipes, have no size to check, and we read them until EOF.
+pub(crate) fn read_file_range(
+    path: &Path,
+    offset: Option<u64>,
+    length: Option<u64>,
+    buffer_len: usize,
+    parallel: bool,
+    mut update: impl FnMut(&[u8]),
+) -> PyResult<()> {
+    let mut file = File::open(path).map_err(|e| os_error(path, e))?;
+    let metadata = file.metadata().map_err(|e| os_error(path, e))?;
+    if !metadata.is_file() {
+        if offset.is_some() || length.is_some() {
+            return Err(PyValueError::new_err(RANGE_NOT_REGULAR_FILE));
+        }
+        let mut buffer = vec![0; buffer_len];
+        loop {
+            match io::Read::read(&mut file, &mut buffer) {
+                Ok(0) => return Ok(()),
+                Ok(n) => update(&buffer[..n]),
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+                Err(e) => return Err(os_error(path, e)),
+            }
+        }
+    }
+
+    let file_len = metadata.len();
+    let offset = offset.unwrap_or(0);
+    let end = offset + checked_range_len(file_len, offset, length)?;
+    let changed_size = || {
+        PyOSError::new_err(format!(
+            "{} changed size while it was being hashed",
+            path.display()
+        ))
+    };
+    let round_len = if parallel {
+        buffer_len * rayon::current_num_threads()
+    } else {
+        buffer_len
+    };
+    let mut buffer = vec![0; usize::try_from(end - offset).map_or(round_len, |n| n.min(round_len))];
+    let mut position = offset;
+    while position < end {
+        let round_len =
+            usize::try_from(end - position).map_or(buffer.len(), |n| n.min(buffer.len()));
+        let round = &mut buffer[..round_len];
+        let result = if parallel {
+            round
+                .par_chunks_mut(buffer_len)
+                .enumerate()
+                .try_for_each(|(i, piece)| {
+                    read_exact_at(&file, piece, position + (i * buffer_len) as u64)
+                })
+        } else {
+            read_exact_at(&file, round, position)
+        };
+        if let Err(e) = result {
+            if e.kind() == io::ErrorKind::UnexpectedEof {
+                return Err(changed_size());
+            }
+            return Err(os_error(path, e));
+        }
+        update(round);
+        position += round_len as u64;
+    }
+    // A file that grew while we were reading it might have been rewritten
+    // underneath us, so check its size again at the end.
+    if file.metadata().map_err(|e| os_error(path, e))?.len() != file_len {
+        return Err(changed_size());
+    }
+    Ok(())
+}
+
+/// Get a `File` for a Python file descriptor without taking ownership of it.
+/// The caller must not let the `File` outlive the Python object that owns the
+/// descriptor, and it must never drop it, which would close the descriptor.
+pub(crate) fn borrow_fd(py: Python, fd: i32) -> PyResult<ManuallyDrop<File>> {
+    #[cfg(unix)]
+    {
+        use std::os::fd::FromRawFd;
+        let _ = py;
+        if fd < 0 {
+            return Err(PyValueError::new_err("file descriptor cannot be negative"));
+        }
+        Ok(ManuallyDrop::new(unsafe { File::from_raw_fd(fd) }))
+    }
+    #[cfg(windows)]
+    {
+        use std::os::windows::io::{FromRawHandle, RawHandle};
+        // Python file descriptors on Windows come from the C runtime, and
+        // msvcrt knows how to map them to OS handles. This raises OSError for
+        // invalid descriptors.
+        let handle: isize = py
+            .import("msvcrt")?
+            .call_method1("get_osfhandle", (fd,))?
+            .extract()?;
+        Ok(ManuallyDrop::new(unsafe {
+            File::from_raw_handle(handle as RawHandle)
+        }))
+    }
+}
+
+/// Read from a specific offset in a file, without using or changing the file
+/// position. On Windows there's no such thing as a positional read that
+/// leaves the file position alone, so there the position ends up after the
+/// bytes that were read.
+pub(crate) fn read_at(file: &File, buf: &mut [u8], offset: u64) -> io::Result<usize> {
+    #[cfg(unix)]
+    return std::os::unix::fs::FileExt::read_at(file, buf, offset);
+    #[cfg(windows)]
+    return std::os::windows::fs::FileExt::seek_read(file, buf, offset);
+}
+
+/// Like `read_at`, but fill the whole buffer, or fail with `UnexpectedEof` if
+/// the file is too short.
+pub(crate) fn read_exact_at(file: &File, mut buf: &mut [u8], mut offset: u64) -> io::Result<()> {
+    while !buf.is_empty() {
+        match read_at(file, buf, offset) {
+            Ok(0) => {
+                return Err(io::Error::new(
+                    io::ErrorKind::UnexpectedEof,
+                    "the file ended early",
+                ));
+            }
+            Ok(n) => {
+                buf = &mut buf[n..];
+                offset += n as u64;
+            }
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(())
+}
+
+/// A shell-style glob pattern. `*` matches any run of characters within one
+/// path component, `?` matches any single character, `[...]` and `[!...]`
+/// match character classes, and a `**` component matches any number of
+/// components. Patterns without a `/` match just the file name, and other
+/// patterns match the whole path relative to the directory being walked.
+#[derive(Clone, Debug)]
+pub(crate) struct Glob {
+    components: Vec<Vec<char>>,
+    name_only: bool,
+}
+
+impl Glob {
+    pub(crate) fn new(pattern: &str) -> PyResult<Self> {
+        let invalid = |msg: &str| PyValueError::new_err(format!("invalid glob {pattern:?}: {msg}"));
+        let trimmed = pattern.trim_start_matches('/');
+        if trimmed.is_empty() {
+            return Err(invalid("the pattern is empty"));
+        }
+        let components: Vec<Vec<char>> = trimmed.split('/').map(|c| c.chars().collect()).collect();
+        for component in &components {
+            if component.is_empty() {
+                return Err(invalid("empty path component"));
+            }
+            check_classes(component).map_err(invalid)?;
+        }
+        Ok(Self {
+            name_only: components.len() == 1 && !pattern.starts_with('/'),
+            components,
+        })
+    }
+
+    /// Match a relative path whose components are separated by `/`.
+    fn matches(&self, relative_path: &str) -> bool {
+        let path_components: Vec<Vec<char>> = relative_path
+            .split('/')
+            .map(|c| c.chars().collect())
+            .collect();
+        if self.name_only {
+            let name = path_components.last().expect("split is never empty");
+            return match_component(&self.components[0], name);
+        }
+        match_components(&self.components, &path_components)
+    }
+}
+
+/// Check that every `[` in a component has a matching `]`.
+fn check_classes(component: &[char]) -> Result<(), &'static str> {
+    let mut i = 0;
+    while i < component.len() {
+        if component[i] == '[' {
+            match parse_class(component, i) {
+                Some((_, _, end)) => i = end,
+                None => return Err("unclosed character class"),
+            }
+        } else {
+            i += 1;
+        }
+    }
+    Ok(())
+}
+
+/// Parse the character class starting at `pattern[start] == '['`. Return the
+/// class contents, whether it's negated, and the index just past the `]`. As
+/// in the shell, a `]` right after the `[` or `[!` is part of the class.
+fn parse_class(pattern: &[char], start: usize) -> Option<(&[char], bool, usize)> {
+    let mut i = start + 1;
+    let negated = pattern.get(i) == Some(&'!');
+    if negated {
+        i += 1;
+    }
+    let contents_start = i;
+    if pattern.get(i) == Some(&']') {
+        i += 1;
+    }
+    while i < pattern.len() {
+        if pattern[i] == ']' {
+            return Some((&pattern[contents_start..i], negated, i + 1));
+        }
+        i += 1;
+    }
+    None
+}
+
+fn class_contains(class: &[char], c: char) -> bool {
+    let mut i = 0;
+    while i < class.len() {
+        if i + 2 < class.len() && class[i + 1] == '-' {
+            if class[i] <= c && c <= class[i + 2] {
+                return true;
+            }
+            i += 3;
+        } else {
+            if class[i] == c {
+                return true;
+            }
+            i += 1;
+        }
+    }
+    false
+}
+
+/// Match one path component against one pattern component, backtracking to
+/// the most recent `*` on a mismatch.
+fn match_component(pattern: &[char], text: &[char]) -> bool {
+    let (mut p, mut t) = (0, 0);
+    let mut last_star: Option<(usize, usize)> = None;
+    while t < text.len() {
+        let step = match pattern.get(p) {
+            Some('*') => {
+                last_star = Some((p, t));
+                p += 1;
+                continue;
+            }
+            Some('?') => Some(p + 1),
+            Some('[') => {
+                let (class, negated, end) = parse_class(pattern, p).expect("checked in Glob::new");
+                (class_contains(class, text[t]) != negated).then_some(end)
+            }
+            Some(&c) => (c == text[t]).then_some(p + 1),
+            None => None,
+        };
+        match (step, last_star) {
+            (Some(next_p), _) => {
+                p = next_p;
+                t += 1;
+            }
+            // Let the last star swallow one more character and try again.
+            (None, Some((star_p, star_t))) => {
+                last_star = Some((star_p, star_t + 1));
+                p = star_p + 1;
+                t = star_t + 1;
+            }
+            (None, None) => return false,
+        }
+    }
+    pattern[p..].iter().all(|&c| c == '*')
+}
+
+fn match_components(pattern: &[Vec<char>], path: &[Vec<char>]) -> bool {
+    match pattern.split_first() {
+        None => path.is_empty(),
+        Some((first, rest)) if first[..] == ['*', '*'] => {
+            (0..=path.len()).any(|skip| match_components(rest, &path[skip..]))
+        }
+        Some((first, rest)) => match path.split_first() {
+            Some((name, path_rest)) => {
+                match_component(first, name) && match_components(rest, path_rest)
+            }
+            None => false,
+        },
+    }
+}
+
+/// Options for walking directories.
+pub(crate) struct WalkOptions {
+    /// Follow symlinks found inside directories. Symlinks given directly as
+    /// roots are always followed.
+    pub(crate) follow_symlinks: bool,
+    /// If non-empty, only files matching at least one of these are included.
+    pub(crate) include: Vec<Glob>,
+    /// Files and directories matching any of these are skipped.
+    pub(crate) exclude: Vec<Glob>,
+}
+
+/// One directory that's being walked. `root` is the directory that was
+/// originally given, which glob patterns are relative to. `canonical` is only
+/// set when following symlinks, to detect loops.
+struct DirFrame {
+    entries: std::vec::IntoIter<fs::DirEntry>,
+    root: Arc<Path>,
+    canonical: Option<PathBuf>,
+}
+
+/// An iterator over all the files under a list of roots, in order. Roots that
+/// are files are yielded as-is, and roots that are directories are walked
+/// recursively, in sorted order. Special files like pipes and sockets inside
+/// directories are skipped. Errors are yielded along with the path they
+/// happened at, and the walk continues after them.
+pub(crate) struct Walk {
+    roots: std::vec::IntoIter<PathBuf>,
+    stack: Vec<DirFrame>,
+    options: WalkOptions,
+}
+
+pub(crate) type WalkItem = Result<PathBuf, (PathBuf, io::Error)>;
+
+impl Walk {
+    pub(crate) fn new(roots: Vec<PathBuf>, options: WalkOptions) -> Self {
+        Self {
+            roots: roots.into_iter(),
+            stack: Vec::new(),
+            options,
+        }
+    }
+
+    fn push_dir(&mut self, dir: &Path, root: Arc<Path>) -> io::Result<()> {
+        // When following symlinks, skip a directory that's the same as one of
+        // the directories above it, so that a symlink loop is only walked
+        // once.
+        let mut canonical = None;
+        if self.options.follow_symlinks {
+            let path = fs::canonicalize(dir)?;
+            if self
+                .stack
+                .iter()
+                .any(|f| f.canonical.as_ref() == Some(&path))
+            {
+                return Ok(());
+            }
+            canonical = Some(path);
+        }
+        let mut entries = fs::read_dir(dir)?.collect::<io::Result<Vec<_>>>()?;
+        entries.sort_by_key(|entry| entry.file_name());
+        self.stack.push(DirFrame {
+            entries: entries.into_iter(),
+            root,
+            canonical,
+        });
+        Ok(())
+    }
+}
+
+impl Iterator for Walk {
+    type Item = WalkItem;
+
+    fn next(&mut self) -> Option<WalkItem> {
+        loop {
+            let Some(frame) = self.stack.last_mut() else {
+                let root = self.roots.next()?;
+                match fs::metadata(&root) {
+                    Ok(metadata) if metadata.is_dir() => {
+                        let root_arc = Arc::from(root.as_path());
+                        if let Err(e) = self.push_dir(&root, root_arc) {
+                            return Some(Err((root, e)));
+                        }
+                        continue;
+                    }
+                    Ok(_) => return Some(Ok(root)),
+                    Err(e) => return Some(Err((root, e))),
+                }
+            };
+            let Some(entry) = frame.entries.next() else {
+                self.stack.pop();
+                continue;
+            };
+            let path = entry.path();
+            let file_type = match entry.file_type() {
+                Ok(file_type) if file_type.is_symlink() => {
+                    if !self.options.follow_symlinks {
+                        continue;
+                    }
+                    match fs::metadata(&path) {
+                        Ok(metadata) => metadata.file_type(),
+                        Err(e) => return Some(Err((path, e))),
+                    }
+                }
+                Ok(file_type) => file_type,
+                Err(e) => return Some(Err((path, e))),
+            };
+            let relative: Vec<String> = path
+                .strip_prefix(&frame.root)
+                .unwrap_or(&path)
+                .components()
+                .map(|c| c.as_os_str().to_string_lossy().into_owned())
+                .collect();
+            let relative = relative.join("/");
+            if self.options.exclude.iter().any(|g| g.matches(&relative)) {
+                continue;
+            }
+            if file_type.is_dir() {
+                let root = frame.root.clone();
+                if let Err(e) = self.push_dir(&path, root) {
+                    return Some(Err((path, e)));
+                }
+                continue;
+            }
+            if !file_type.is_file() {
+                continue;
+            }
+            let include = &self.options.include;
+            if include.is_empty() || include.iter().any(|g| g.matches(&relative)) {
+                return Some(Ok(path));
+            }
+        }
+    }
+}
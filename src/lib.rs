@@ -1,15 +1,45 @@
 extern crate blake3 as upstream_blake3;
 
-use pyo3::buffer::PyBuffer;
-use pyo3::exceptions::{PyBufferError, PyOverflowError, PyValueError};
+mod checksums;
+mod encoding;
+mod files;
+mod multiformats;
+mod rows;
+mod strided;
+mod text;
+
+use encoding::Encoding;
+use pyo3::buffer::{PyBuffer, PyUntypedBuffer};
+use pyo3::exceptions::{
+    PyBlockingIOError, PyBufferError, PyOverflowError, PyTypeError, PyValueError,
+};
 use pyo3::prelude::*;
-use pyo3::types::{PyAny, PyBytes, PyString};
-use std::path::PathBuf;
-use std::sync::Mutex;
+use pyo3::types::{PyAny, PyBool, PyBytes, PyDict, PyList, PyString, PyTuple, PyType};
+use rayon::prelude::*;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU8, Ordering};
+use std::sync::{Mutex, mpsc};
+use strided::{Order, StridedBytes};
+use text::TextEncoding;
+
+pyo3::import_exception!(io, UnsupportedOperation);
+pyo3::create_exception!(
+    blake3.blake3,
+    VerificationError,
+    PyValueError,
+    "Raised by `blake3.verify` when the output doesn't match."
+);
 
 // This is the same as HASHLIB_GIL_MINSIZE in CPython.
 const GIL_MINSIZE: usize = 2048;
 
+// Extended outputs at least this long get split up across threads, when the
+// hasher is multithreaded. Each thread gets pieces of XOF_RAYON_CHUNK_LEN
+// bytes, a multiple of the 64-byte BLAKE3 block size.
+const XOF_RAYON_MIN_LEN: usize = 128 * 1024;
+const XOF_RAYON_CHUNK_LEN: usize = 64 * 1024;
+
 // We want to support buffers of both signed and unsigned bytes, and for hashing
 // purposes we'll pointer cast both to &[u8]. PyO3 gives us typed buffers, so we
 // use this enum to wrap them.
@@ -49,6 +79,58 @@ impl BytesPyBuffer {
             Self::I8(pybuffer) => unsafe { bytes_from_pybuffer(pybuffer) },
         }
     }
+
+    /// Like `get`, but fail if the buffer is read-only. This is for methods
+    /// like `readinto` that write output into a caller-provided buffer.
+    fn get_writable(data: &Bound<PyAny>) -> PyResult<Self> {
+        let buf = Self::get(data)?;
+        let readonly = match &buf {
+            Self::U8(pybuffer) => pybuffer.readonly(),
+            Self::I8(pybuffer) => pybuffer.readonly(),
+        };
+        if readonly {
+            return Err(PyBufferError::new_err("buffer is read-only"));
+        }
+        Ok(buf)
+    }
+
+    /// The buffer's shape, e.g. `[rows, columns]` for a 2-D array.
+    fn shape(&self) -> &[usize] {
+        match self {
+            Self::U8(pybuffer) => pybuffer.shape(),
+            Self::I8(pybuffer) => pybuffer.shape(),
+        }
+    }
+
+    /// Like `as_bytes`, but also accept buffers that aren't C-contiguous, and
+    /// read the items of multidimensional buffers in the given order. Buffers
+    /// that are contiguous in that order are still a plain slice. This has all
+    /// the same safety issues as `as_bytes`.
+    unsafe fn as_input_bytes(&self, order: Order) -> PyResult<InputBytes<'_>> {
+        match self {
+            Self::U8(pybuffer) => unsafe { input_bytes_from_pybuffer(pybuffer, order, false) },
+            Self::I8(pybuffer) => unsafe { input_bytes_from_pybuffer(pybuffer, order, false) },
+        }
+    }
+
+    /// Get a &mut [u8] from a writable PyBuffer<u8> or PyBuffer<i8>. This has
+    /// all the same safety issues as `as_bytes`, and the caller must also have
+    /// gotten this buffer from `get_writable`.
+    // The returned slice points to memory owned by the Python object, not by
+    // the PyBuffer handle, so handing out &mut from &self is intentional.
+    #[allow(clippy::mut_from_ref)]
+    unsafe fn as_bytes_mut(&self) -> PyResult<&mut [u8]> {
+        // Go through as_bytes for the contiguity check, but build the mutable
+        // slice from the original pointer. We never create any other
+        // references to this memory ourselves. See the comments in
+        // bytes_from_pybuffer about what other threads might do.
+        let len = unsafe { self.as_bytes()?.len() };
+        let ptr = match self {
+            Self::U8(pybuffer) => pybuffer.buf_ptr(),
+            Self::I8(pybuffer) => pybuffer.buf_ptr(),
+        };
+        Ok(unsafe { std::slice::from_raw_parts_mut(ptr as *mut u8, len) })
+    }
 }
 
 unsafe fn bytes_from_pybuffer<T: pyo3::buffer::Element>(pybuffer: &PyBuffer<T>) -> PyResult<&[u8]> {
@@ -85,12 +167,13 @@ unsafe fn bytes_from_pybuffer<T: pyo3::buffer::Element>(pybuffer: &PyBuffer<T>)
     //   once we return it, so we're ok here.
     // - We might accidentally write to this buffer ourselves, by writing to a
     //   "different" buffer that turns out to be an alias, or by running any
-    //   Python code we don't control that happens to do the same thing. We
-    //   won't do any writes to buffers we didn't create internally, and we
-    //   won't run any Python code (even finalizers) while this slice is alive,
-    //   so I think we're ok here too. (We also won't use any invariant-checking
-    //   functions like `std::str::from_utf8` that could get fooled by illegal
-    //   mutable aliasing.)
+    //   Python code we don't control that happens to do the same thing. The
+    //   only caller buffers we write to are the output buffers of methods like
+    //   `digest_into`, and we never read from a buffer in the same call that
+    //   writes to one. We also won't run any Python code (even finalizers)
+    //   while this slice is alive, so I think we're ok here too. (We also won't
+    //   use any invariant-checking functions like `std::str::from_utf8` that
+    //   could get fooled by illegal mutable aliasing.)
     // - Another thread might do the same thing, totally out of our control.
     //   In addition to problems that we can cause by doing this ourselves, this
     //   is also a *data race*, which is per se UB.
@@ -130,6 +213,9 @@ unsafe fn bytes_from_pybuffer<T: pyo3::buffer::Element>(pybuffer: &PyBuffer<T>)
     // trigger it. Writing to a buffer concurrently from another thread while
     // hashing it is a very weird thing to do, and it's almost guaranteed to be
     // a correctness bug, regardless of whether it's also a soundness bug.
+    // Callers who want defined behavior anyway can opt into the `snapshot`
+    // mode of the `blake3` class, which copies inputs before releasing the
+    // GIL.
     unsafe {
         Ok(std::slice::from_raw_parts(
             pybuffer.buf_ptr() as *const u8,
@@ -138,6 +224,246 @@ unsafe fn bytes_from_pybuffer<T: pyo3::buffer::Element>(pybuffer: &PyBuffer<T>)
     }
 }
 
+/// Input bytes from a buffer, either as one slice or, for buffers that aren't
+/// contiguous in the order we're reading them, a strided view that we gather
+/// through a staging buffer as we hash it. See `BytesPyBuffer::as_input_bytes`.
+enum InputBytes<'a> {
+    Slice(&'a [u8]),
+    Strided(StridedBytes<'a>),
+}
+
+impl InputBytes<'_> {
+    fn len(&self) -> usize {
+        match self {
+            InputBytes::Slice(slice) => slice.len(),
+            InputBytes::Strided(strided) => strided.len(),
+        }
+    }
+
+    /// Append all the bytes to `out`, in order.
+    fn copy_into(&self, out: &mut Vec<u8>) {
+        match self {
+            InputBytes::Slice(slice) => out.extend_from_slice(slice),
+            InputBytes::Strided(strided) => {
+                strided.for_each_piece(READ_BUFFER_LEN, |piece| out.extend_from_slice(piece))
+            }
+        }
+    }
+
+    /// Check whether the bytes are the same as the start of `expected`, and
+    /// return the rest of `expected`, or None if they're different.
+    fn compare<'b>(&self, expected: &'b [u8]) -> Option<&'b [u8]> {
+        match self {
+            InputBytes::Slice(slice) => expected.strip_prefix(*slice),
+            InputBytes::Strided(strided) => {
+                let mut rest = Some(expected);
+                strided.for_each_piece(READ_BUFFER_LEN, |piece| {
+                    rest = rest.and_then(|rest| rest.strip_prefix(piece));
+                });
+                rest
+            }
+        }
+    }
+}
+
+/// Whether to copy input buffers before hashing them with the GIL released.
+/// See `set_snapshot_default`.
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum SnapshotMode {
+    Off,
+    Copy,
+    Detect,
+}
+
+/// The `SnapshotMode` for new hashers, stored as a u8 so that it can be an
+/// atomic.
+static SNAPSHOT_DEFAULT: AtomicU8 = AtomicU8::new(SnapshotMode::Off as u8);
+
+impl SnapshotMode {
+    /// Interpret a `snapshot` argument: False, True, or "detect".
+    fn from_py(snapshot: &Bound<PyAny>) -> PyResult<Self> {
+        if let Ok(snapshot) = snapshot.cast::<PyBool>() {
+            return Ok(if snapshot.is_true() {
+                SnapshotMode::Copy
+            } else {
+                SnapshotMode::Off
+            });
+        }
+        match snapshot.extract::<&str>() {
+            Ok("detect") => Ok(SnapshotMode::Detect),
+            _ => Err(PyValueError::new_err(
+                "snapshot must be True, False, or \"detect\"",
+            )),
+        }
+    }
+
+    fn into_py(self, py: Python) -> Bound<PyAny> {
+        match self {
+            SnapshotMode::Off => PyBool::new(py, false).to_owned().into_any(),
+            SnapshotMode::Copy => PyBool::new(py, true).to_owned().into_any(),
+            SnapshotMode::Detect => PyString::new(py, "detect").into_any(),
+        }
+    }
+
+    fn default() -> Self {
+        match SNAPSHOT_DEFAULT.load(Ordering::Relaxed) {
+            0 => SnapshotMode::Off,
+            1 => SnapshotMode::Copy,
+            _ => SnapshotMode::Detect,
+        }
+    }
+}
+
+/// Get the input bytes of any buffer, with items read in the given order, and
+/// optionally with the bytes of each item reversed. See `as_input_bytes`.
+unsafe fn input_bytes_from_pybuffer(
+    pybuffer: &PyUntypedBuffer,
+    order: Order,
+    swap: bool,
+) -> PyResult<InputBytes<'_>> {
+    let contiguous = match order {
+        Order::C => pybuffer.is_c_contiguous(),
+        Order::F => pybuffer.is_fortran_contiguous(),
+        Order::A => pybuffer.is_c_contiguous() || pybuffer.is_fortran_contiguous(),
+    };
+    if contiguous && !swap {
+        // For a buffer that's both C- and F-contiguous, or only F-contiguous
+        // with order "F" or "A", the memory order is the order we want.
+        return Ok(InputBytes::Slice(unsafe {
+            std::slice::from_raw_parts(pybuffer.buf_ptr() as *const u8, pybuffer.len_bytes())
+        }));
+    }
+    // PIL-style arrays of pointers are very rare, and even memoryview doesn't
+    // support them in most places.
+    if pybuffer
+        .suboffsets()
+        .is_some_and(|suboffsets| suboffsets.iter().any(|&s| s >= 0))
+    {
+        return Err(PyBufferError::new_err(
+            "buffers with suboffsets are not supported",
+        ));
+    }
+    // Treat the buffer as C order for "A" unless it's only F-contiguous, like
+    // `memoryview.tobytes` does.
+    let order = match order {
+        Order::A if !pybuffer.is_c_contiguous() && pybuffer.is_fortran_contiguous() => Order::F,
+        Order::A => Order::C,
+        order => order,
+    };
+    // XXX: The same safety issues as bytes_from_pybuffer apply to reading
+    // through this view.
+    Ok(InputBytes::Strided(unsafe {
+        StridedBytes::new(
+            pybuffer.buf_ptr() as *const u8,
+            pybuffer.shape(),
+            pybuffer.strides(),
+            pybuffer.item_size(),
+            order,
+            swap,
+        )
+    }))
+}
+
+/// Hex encode the first half of `buf` into the whole buffer. We work from
+/// back to front, so each byte is read before anything overwrites it.
+fn hex_encode_in_place(buf: &mut [u8]) {
+    const HEX_CHARS: &[u8; 16] = b"0123456789abcdef";
+    debug_assert_eq!(buf.len() % 2, 0);
+    for i in (0..buf.len() / 2).rev() {
+        let byte = buf[i];
+        buf[2 * i] = HEX_CHARS[(byte >> 4) as usize];
+        buf[2 * i + 1] = HEX_CHARS[(byte & 0xf) as usize];
+    }
+}
+
+/// Fill `output` from the reader's current position, releasing the GIL for
+/// long outputs.
+fn fill_output(
+    py: Python,
+    threading_mode: &ThreadingMode,
+    reader: &mut upstream_blake3::OutputReader,
+    output: &mut [u8],
+) {
+    if output.len() >= GIL_MINSIZE {
+        // This could be a long-running operation. Release the GIL.
+        py.detach(|| threading_mode.fill(reader, output));
+    } else {
+        // Don't bother releasing the GIL for short outputs.
+        threading_mode.fill(reader, output);
+    }
+}
+
+/// Fill `output` from the reader's current position using the current rayon
+/// pool. The output stream is seekable, so each worker can clone the reader
+/// and seek its clone to the start of its own piece of the output.
+fn fill_rayon(reader: &upstream_blake3::OutputReader, output: &mut [u8]) {
+    let start = reader.position();
+    output
+        .par_chunks_mut(XOF_RAYON_CHUNK_LEN)
+        .enumerate()
+        .for_each(|(i, chunk)| {
+            let mut reader = reader.clone();
+            // The output stream doesn't really end at 2**64 bytes, so wrap
+            // around at the end like a sequential `fill` would.
+            reader.set_position(start.wrapping_add((i * XOF_RAYON_CHUNK_LEN) as u64));
+            reader.fill(chunk);
+        });
+}
+
+/// Create an upstream Hasher in whichever mode the `key` and
+/// `derive_key_context` arguments select, like the `blake3` constructor.
+fn new_rust_hasher(
+    key: Option<&Bound<PyAny>>,
+    derive_key_context: Option<&str>,
+) -> PyResult<upstream_blake3::Hasher> {
+    match (key, derive_key_context) {
+        // The default, unkeyed hash function.
+        (None, None) => Ok(upstream_blake3::Hasher::new()),
+        // The keyed hash function.
+        (Some(key_obj), None) => {
+            let key_array = key_from_pyobject(key_obj)?;
+            Ok(upstream_blake3::Hasher::new_keyed(&key_array))
+        }
+        // The key derivation function.
+        (None, Some(context)) => Ok(upstream_blake3::Hasher::new_derive_key(context)),
+        // Error: can't use both modes at the same time.
+        (Some(_), Some(_)) => Err(PyValueError::new_err(
+            "cannot use key and derive_key_context at the same time",
+        )),
+    }
+}
+
+/// Like `new_rust_hasher`, but for `hash_rows`.
+fn new_row_hasher(
+    key: Option<&Bound<PyAny>>,
+    derive_key_context: Option<&str>,
+) -> PyResult<rows::RowHasher> {
+    match (key, derive_key_context) {
+        (None, None) => Ok(rows::RowHasher::new()),
+        (Some(key_obj), None) => Ok(rows::RowHasher::new_keyed(&key_from_pyobject(key_obj)?)),
+        (None, Some(context)) => Ok(rows::RowHasher::new_derive_key(context)),
+        (Some(_), Some(_)) => Err(PyValueError::new_err(
+            "cannot use key and derive_key_context at the same time",
+        )),
+    }
+}
+
+/// Copy a 32-byte key out of any buffer, or raise ValueError if the length is
+/// wrong.
+fn key_from_pyobject(key_obj: &Bound<PyAny>) -> PyResult<[u8; 32]> {
+    // Use the same `as_bytes` helper function to get the key buffer as
+    // `update` uses to get the data buffer. Even though we just copy the bytes
+    // immediately here, technically this risks the same race conditions.
+    let key_buf = BytesPyBuffer::get(key_obj)?;
+    let key_slice: &[u8] = unsafe { key_buf.as_bytes()? };
+    if let Ok(array) = key_slice.try_into() {
+        Ok(array)
+    } else {
+        let msg = format!("expected a {}-byte key, found {}", 32, key_slice.len());
+        Err(PyValueError::new_err(msg))
+    }
+}
+
 fn new_thread_pool(max_threads: usize) -> rayon::ThreadPool {
     rayon::ThreadPoolBuilder::new()
         .num_threads(max_threads)
@@ -154,6 +480,111 @@ enum ThreadingMode {
     },
 }
 
+impl ThreadingMode {
+    /// Interpret a `max_threads` argument.
+    fn new(max_threads: isize) -> PyResult<Self> {
+        match max_threads {
+            1 => Ok(ThreadingMode::Single),
+            Blake3Class::AUTO => Ok(ThreadingMode::Auto),
+            n if n > 1 => Ok(ThreadingMode::Pool {
+                max_threads: n as usize,
+                pool: new_thread_pool(n as usize),
+            }),
+            _ => Err(PyValueError::new_err("not a valid number of threads")),
+        }
+    }
+
+    /// Add input to a hasher, using multiple threads if this mode allows it.
+    fn update(&self, hasher: &mut upstream_blake3::Hasher, input: &[u8]) {
+        match self {
+            ThreadingMode::Single => {
+                hasher.update(input);
+            }
+            ThreadingMode::Auto => {
+                hasher.update_rayon(input);
+            }
+            ThreadingMode::Pool { pool, .. } => pool.install(|| {
+                hasher.update_rayon(input);
+            }),
+        }
+    }
+
+    /// Add the contents of a file to a hasher. Upstream memory maps the file
+    /// if it's a regular file and large enough to benefit, and otherwise
+    /// falls back to buffered reads.
+    fn update_mmap(&self, hasher: &mut upstream_blake3::Hasher, path: &Path) -> io::Result<()> {
+        match self {
+            ThreadingMode::Single => {
+                hasher.update_mmap(path)?;
+            }
+            ThreadingMode::Auto => {
+                hasher.update_mmap_rayon(path)?;
+            }
+            ThreadingMode::Pool { pool, .. } => {
+                pool.install(|| hasher.update_mmap_rayon(path))?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Like `update_mmap`, but optionally for just part of the file. The
+    /// range must be within the file, and large ranges are still memory
+    /// mapped and hashed with multiple threads if this mode allows it.
+    fn update_mmap_range(
+        &self,
+        hasher: &mut upstream_blake3::Hasher,
+        path: &Path,
+        offset: Option<u64>,
+        length: Option<u64>,
+    ) -> PyResult<()> {
+        if offset.is_none() && length.is_none() {
+            self.update_mmap(hasher, path)?;
+        } else {
+            let range = files::file_range(path, offset.unwrap_or(0), length)?;
+            self.update(hasher, &range);
+        }
+        Ok(())
+    }
+
+    /// Like `update_mmap_range`, but with positional reads instead of a
+    /// memory map, so that a file getting truncated is an error rather than a
+    /// crash. See `files::read_file_range`. Reads are parallel if this mode
+    /// allows it.
+    fn update_path(
+        &self,
+        hasher: &mut upstream_blake3::Hasher,
+        path: &Path,
+        offset: Option<u64>,
+        length: Option<u64>,
+    ) -> PyResult<()> {
+        let buffer_len = default_read_buffer_len(self);
+        let parallel = !matches!(self, ThreadingMode::Single);
+        let mut read = || {
+            files::read_file_range(path, offset, length, buffer_len, parallel, |data| {
+                self.update(hasher, data)
+            })
+        };
+        match self {
+            ThreadingMode::Pool { pool, .. } => pool.install(read),
+            _ => read(),
+        }
+    }
+
+    /// Fill `output` from the reader's current position, splitting long
+    /// outputs up across threads if this mode allows it.
+    fn fill(&self, reader: &mut upstream_blake3::OutputReader, output: &mut [u8]) {
+        if output.len() < XOF_RAYON_MIN_LEN {
+            reader.fill(output);
+            return;
+        }
+        match self {
+            ThreadingMode::Single => reader.fill(output),
+            ThreadingMode::Auto => fill_rayon(reader, output),
+            ThreadingMode::Pool { pool, .. } => pool.install(|| fill_rayon(reader, output)),
+        }
+    }
+}
+
 impl Clone for ThreadingMode {
     fn clone(&self) -> Self {
         match self {
@@ -167,6 +598,92 @@ impl Clone for ThreadingMode {
     }
 }
 
+/// Multithreaded hashers gather short updates until they have at least this
+/// many bytes. `update_rayon` has a lot of overhead for short inputs, and
+/// without this, a stream of small writes (like 4 KiB network reads) would
+/// never get any help from the other threads. This is a multiple of the
+/// 1 KiB chunk size, so the hasher stays aligned to large power-of-two
+/// subtrees as long as the updates are small.
+const COALESCE_LEN: usize = 1024 * 1024;
+
+/// The state behind a `blake3` hasher's lock. Multithreaded hashers gather
+/// short updates in `pending` before passing them to `hasher`, so anything
+/// that reads `hasher` has to go through `flushed`. Single-threaded hashers
+/// never have anything pending.
+#[derive(Clone)]
+struct HasherState {
+    hasher: upstream_blake3::Hasher,
+    pending: Vec<u8>,
+}
+
+impl HasherState {
+    fn new(hasher: upstream_blake3::Hasher) -> Self {
+        Self {
+            hasher,
+            pending: Vec::new(),
+        }
+    }
+
+    /// Add input to the hasher, or to the pending bytes if it's short and
+    /// this mode is multithreaded.
+    fn update(&mut self, threading_mode: &ThreadingMode, mut input: &[u8]) {
+        if matches!(threading_mode, ThreadingMode::Single) {
+            threading_mode.update(&mut self.hasher, input);
+            return;
+        }
+        if !self.pending.is_empty() || input.len() < COALESCE_LEN {
+            let take = input.len().min(COALESCE_LEN - self.pending.len());
+            self.pending.extend_from_slice(&input[..take]);
+            input = &input[take..];
+            if self.pending.len() < COALESCE_LEN {
+                return;
+            }
+            threading_mode.update(&mut self.hasher, &self.pending);
+            self.pending.clear();
+        }
+        if input.len() >= COALESCE_LEN {
+            threading_mode.update(&mut self.hasher, input);
+        } else {
+            self.pending.extend_from_slice(input);
+        }
+    }
+
+    /// Like `update`, but for input that might need to be gathered from a
+    /// strided buffer first. The staging buffer is the same size we use for
+    /// reading files, which is big enough to split up across threads.
+    fn update_input(&mut self, threading_mode: &ThreadingMode, input: &InputBytes) {
+        match input {
+            InputBytes::Slice(slice) => self.update(threading_mode, slice),
+            InputBytes::Strided(strided) => strided
+                .for_each_piece(default_read_buffer_len(threading_mode), |piece| {
+                    self.update(threading_mode, piece)
+                }),
+        }
+    }
+
+    /// Whether an update of `input_len` bytes would only add to the pending
+    /// bytes, without hashing anything. Updates like that can be undone by
+    /// truncating `pending`.
+    fn only_buffers(&self, threading_mode: &ThreadingMode, input_len: usize) -> bool {
+        !matches!(threading_mode, ThreadingMode::Single)
+            && self.pending.len() + input_len < COALESCE_LEN
+    }
+
+    /// Hash anything pending, and return the up-to-date hasher.
+    fn flushed(&mut self, threading_mode: &ThreadingMode) -> &mut upstream_blake3::Hasher {
+        if !self.pending.is_empty() {
+            threading_mode.update(&mut self.hasher, &self.pending);
+            self.pending.clear();
+        }
+        &mut self.hasher
+    }
+
+    fn reset(&mut self) {
+        self.hasher.reset();
+        self.pending.clear();
+    }
+}
+
 /// An incremental BLAKE3 hasher, which can accept any number of writes.
 /// The interface is similar to `hashlib.blake2b` or `hashlib.md5` from the
 /// standard library.
@@ -191,6 +708,13 @@ impl Clone for ThreadingMode {
 ///   may also ignore this parameter entirely, if they don't support
 ///   multithreading.
 /// - `usedforsecurity`: Currently ignored. See the standard hashlib docs.
+/// - `order`: The order to read the items of a multidimensional `data`
+///   buffer in. See `update`.
+/// - `snapshot`: Whether to copy input buffers before hashing them with the
+///   GIL released, so that other threads writing to a buffer can't race with
+///   hashing it. True copies every input, and "detect" also raises
+///   `BufferError` if a buffer changed while it was being hashed. The default
+///   is False, unless `set_snapshot_default` has changed it.
 // Note: The "blake3.blake3.blake3" canonical path is a Maturin implementation detail. See
 // https://github.com/mkdocstrings/mkdocstrings/issues/451 for why we expose it here. That means
 // that both of these work today, though most callers should prefer the first one:
@@ -224,8 +748,162 @@ struct Blake3Class {
     // Hasher. This means that users will never see exceptions about mutable
     // borrowing, and the PyO3 docs mention that they want to push the ecosystem
     // in this direction. See: https://pyo3.rs/main/class.html#frozen-classes-opting-out-of-interior-mutability
-    rust_hasher: Mutex<upstream_blake3::Hasher>,
+    rust_hasher: Mutex<HasherState>,
     threading_mode: ThreadingMode,
+    snapshot: SnapshotMode,
+}
+
+impl Blake3Class {
+    /// Whether to release the GIL for an update of `input_len` bytes. Short
+    /// updates to a multithreaded hasher usually just add to the pending
+    /// bytes, but the one that fills them up hashes all of them.
+    fn detach_for_update(&self, input_len: usize) -> bool {
+        if input_len >= GIL_MINSIZE {
+            return true;
+        }
+        if matches!(self.threading_mode, ThreadingMode::Single) {
+            return false;
+        }
+        match self.rust_hasher.try_lock() {
+            Ok(state) => state.pending.len() + input_len >= COALESCE_LEN,
+            // Another thread is probably hashing, and we might wait a while.
+            Err(_) => true,
+        }
+    }
+
+    /// Run `f` on the up-to-date hasher, releasing the GIL if there are
+    /// enough pending bytes to be worth it.
+    fn with_flushed<T: Send>(
+        &self,
+        py: Python,
+        f: impl FnOnce(&mut upstream_blake3::Hasher) -> T + Send,
+    ) -> T {
+        let detach = match self.rust_hasher.try_lock() {
+            Ok(state) => state.pending.len() >= GIL_MINSIZE,
+            Err(_) => true,
+        };
+        let flush_closure = || {
+            f(self
+                .rust_hasher
+                .lock()
+                .unwrap()
+                .flushed(&self.threading_mode))
+        };
+        if detach {
+            py.detach(flush_closure)
+        } else {
+            flush_closure()
+        }
+    }
+
+    /// Add input bytes to the hasher, in order, releasing the GIL for long
+    /// inputs. Callers pass `self.snapshot`, unless the inputs are memory
+    /// that we own and no one else can write to.
+    fn update_inputs(
+        &self,
+        py: Python,
+        inputs: &[InputBytes],
+        snapshot: SnapshotMode,
+    ) -> PyResult<()> {
+        let total_len: usize = inputs.iter().map(InputBytes::len).sum();
+        if snapshot == SnapshotMode::Off {
+            let update_closure = || {
+                let mut rust_hasher = self.rust_hasher.lock().unwrap();
+                for input in inputs {
+                    rust_hasher.update_input(&self.threading_mode, input);
+                }
+            };
+            if self.detach_for_update(total_len) {
+                // Release the GIL while we hash this slice, so that we don't
+                // block other threads. But again, see all the comments in
+                // bytes_from_pybuffer about data race risks.
+                py.detach(update_closure);
+            } else {
+                // Don't bother releasing the GIL for short updates.
+                update_closure();
+            }
+            return Ok(());
+        }
+
+        // Copy the input while we still hold the GIL. Other Python threads
+        // can't write to it in the meantime, except on free-threaded builds,
+        // and whatever the copy ends up with, it's what we hash.
+        let mut copy = Vec::with_capacity(total_len);
+        for input in inputs {
+            input.copy_into(&mut copy);
+        }
+        let update_closure = || {
+            let mut rust_hasher = self.rust_hasher.lock().unwrap();
+            if snapshot == SnapshotMode::Copy {
+                rust_hasher.update(&self.threading_mode, &copy);
+                return Ok(());
+            }
+            let unchanged = || {
+                let rest = inputs
+                    .iter()
+                    .try_fold(&copy[..], |rest, input| input.compare(rest));
+                rest == Some(&[][..])
+            };
+            let changed_error =
+                || PyBufferError::new_err("the input buffer changed while it was being hashed");
+            // If the input changed, the hasher should be left unchanged. Most
+            // short updates only add to the pending bytes, and we can undo
+            // that without copying them. Otherwise, hash into a clone.
+            if rust_hasher.only_buffers(&self.threading_mode, copy.len()) {
+                let pending_len = rust_hasher.pending.len();
+                rust_hasher.update(&self.threading_mode, &copy);
+                if !unchanged() {
+                    rust_hasher.pending.truncate(pending_len);
+                    return Err(changed_error());
+                }
+                return Ok(());
+            }
+            let mut new_hasher = rust_hasher.clone();
+            new_hasher.update(&self.threading_mode, &copy);
+            if !unchanged() {
+                return Err(changed_error());
+            }
+            *rust_hasher = new_hasher;
+            Ok(())
+        };
+        if self.detach_for_update(total_len) {
+            py.detach(update_closure)
+        } else {
+            update_closure()
+        }
+    }
+
+    /// Add the bytes of any buffer to the hasher, for `update` and the
+    /// constructor.
+    fn update_pyobject(&self, py: Python, data: &Bound<PyAny>, order: Order) -> PyResult<()> {
+        // Exact `bytes` objects are immutable, so we can read their memory
+        // directly, without the buffer protocol, and they never need a
+        // snapshot. For short inputs, getting a `PyBuffer` costs more than
+        // hashing. We can't do the same for `bytearray`. It can be resized,
+        // and while we're hashing it with the GIL released, only the buffer
+        // protocol's export count stops another thread from doing that and
+        // freeing the memory we're reading. Subclasses of `bytes` might have
+        // their own `__buffer__`, so they take the regular path.
+        if let Ok(bytes) = data.cast_exact::<PyBytes>() {
+            let input = InputBytes::Slice(bytes.as_bytes());
+            return self.update_inputs(py, &[input], SnapshotMode::Off);
+        }
+
+        // XXX: Get a &[u8] slice of the data bytes, or a strided view of
+        // them. The safety situation here is complicated. See all the
+        // comments in bytes_from_pybuffer.
+        let data_buf = BytesPyBuffer::get(data)?;
+        let data_bytes = unsafe { data_buf.as_input_bytes(order)? };
+        self.update_inputs(py, &[data_bytes], self.snapshot)
+    }
+
+    /// Fill `output` with bytes from the output stream, starting at `seek`.
+    /// This releases the GIL for long outputs.
+    fn fill_output(&self, py: Python, output: &mut [u8], seek: u64) {
+        let mut reader = self.with_flushed(py, |hasher| hasher.finalize_xof());
+        reader.set_position(seek);
+        fill_output(py, &self.threading_mode, &mut reader, output);
+    }
 }
 
 #[pymethods]
@@ -265,8 +943,11 @@ impl Blake3Class {
         key = None,
         derive_key_context = None,
         max_threads = 1,
-        usedforsecurity = true
+        usedforsecurity = true,
+        order = "C",
+        snapshot = None
     ))]
+    #[allow(clippy::too_many_arguments)]
     fn new<'py>(
         py: Python<'py>,
         data: Option<&Bound<'py, PyAny>>,
@@ -274,169 +955,442 @@ impl Blake3Class {
         derive_key_context: Option<&str>,
         max_threads: isize,
         usedforsecurity: bool,
+        order: &str,
+        snapshot: Option<&Bound<'py, PyAny>>,
     ) -> PyResult<Blake3Class> {
         let _ = usedforsecurity; // currently ignored
+        let order = Order::from_name(order)?;
 
-        let mut rust_hasher = match (key, derive_key_context) {
-            // The default, unkeyed hash function.
-            (None, None) => upstream_blake3::Hasher::new(),
-            // The keyed hash function.
-            (Some(key_obj), None) => {
-                // Use the same `as_bytes` helper function to get the key buffer
-                // as `update` uses to get the data buffer. Even though we just
-                // copy the bytes immediately here, technically this risks the
-                // same race conditions.
-                let key_buf = BytesPyBuffer::get(key_obj)?;
-                let key_slice: &[u8] = unsafe { key_buf.as_bytes()? };
-                let key_array: &[u8; 32] = if let Ok(array) = key_slice.try_into() {
-                    array
-                } else {
-                    let msg = format!("expected a {}-byte key, found {}", 32, key_slice.len());
-                    return Err(PyValueError::new_err(msg));
-                };
-                upstream_blake3::Hasher::new_keyed(key_array)
-            }
-            // The key derivation function.
-            (None, Some(context)) => upstream_blake3::Hasher::new_derive_key(context),
-            // Error: can't use both modes at the same time.
-            (Some(_), Some(_)) => {
-                return Err(PyValueError::new_err(
-                    "cannot use key and derive_key_context at the same time",
-                ));
-            }
+        let rust_hasher = new_rust_hasher(key, derive_key_context)?;
+
+        let threading_mode = ThreadingMode::new(max_threads)?;
+
+        let snapshot = match snapshot {
+            Some(snapshot) => SnapshotMode::from_py(snapshot)?,
+            None => SnapshotMode::default(),
         };
 
-        let threading_mode = match max_threads {
-            1 => ThreadingMode::Single,
-            Self::AUTO => ThreadingMode::Auto,
-            n if n > 1 => ThreadingMode::Pool {
-                max_threads: n as usize,
-                pool: new_thread_pool(n as usize),
-            },
-            _ => return Err(PyValueError::new_err("not a valid number of threads")),
+        let hasher = Blake3Class {
+            rust_hasher: Mutex::new(HasherState::new(rust_hasher)),
+            threading_mode,
+            snapshot,
         };
 
         if let Some(data_obj) = data {
-            // XXX: Get a &[u8] slice of the data bytes. The safety situation
-            // here is complicated. See all the comments in bytes_from_pybuffer.
-            let data_buf = BytesPyBuffer::get(data_obj)?;
-            let data_slice: &[u8] = unsafe { data_buf.as_bytes()? };
-
-            // Since rust_hasher isn't yet shared, we don't need to access it
-            // through the Mutex here like we do in update() below.
-            let mut update_closure = || match &threading_mode {
-                ThreadingMode::Single => {
-                    rust_hasher.update(data_slice);
-                }
-                ThreadingMode::Auto => {
-                    rust_hasher.update_rayon(data_slice);
-                }
-                ThreadingMode::Pool { pool, .. } => pool.install(|| {
-                    rust_hasher.update_rayon(data_slice);
-                }),
-            };
-
-            if data_slice.len() >= GIL_MINSIZE {
-                // Release the GIL while we hash this slice, so that we don't
-                // block other threads. But again, see all the comments above
-                // about data race risks.
-                py.detach(update_closure);
-            } else {
-                // Don't bother releasing the GIL for short updates.
-                update_closure();
-            }
+            hasher.update_pyobject(py, data_obj, order)?;
         }
 
-        Ok(Blake3Class {
-            rust_hasher: Mutex::new(rust_hasher),
-            threading_mode,
-        })
+        Ok(hasher)
     }
 
     /// Add input bytes to the hasher. You can call this any number of
     /// times.
     ///
     /// Arguments:
-    /// - `data` (required): The input bytes.
-    #[pyo3(signature=(data, /))]
+    /// - `data` (required): The input bytes. This can be any buffer of bytes,
+    ///   including strided and multidimensional ones like NumPy array slices.
+    ///   Buffers that aren't contiguous are copied a piece at a time as
+    ///   they're hashed.
+    /// - `order`: The order to read the items of a multidimensional buffer
+    ///   in, the same as for `memoryview.tobytes`. "C" (the default) means
+    ///   row-major order, "F" means column-major order, and "A" means "F" if
+    ///   the buffer is Fortran-contiguous and "C" otherwise. For example, the
+    ///   hash of a Fortran-ordered NumPy array `a` with `order="A"` is the hash
+    ///   of `a.tobytes(order="A")`, which needs no copying.
+    #[pyo3(signature=(data, /, *, order="C"))]
     fn update<'py>(
         this: Bound<'py, Self>,
         py: Python,
         data: &Bound<PyAny>,
+        order: &str,
     ) -> PyResult<Bound<'py, Self>> {
-        let self_ = this.get();
-
-        // XXX: Get a &[u8] slice of the data bytes. The safety situation here
-        // is complicated. See all the comments in bytes_from_pybuffer.
-        let data_buf = BytesPyBuffer::get(data)?;
-        let data_slice: &[u8] = unsafe { data_buf.as_bytes()? };
+        let order = Order::from_name(order)?;
+        this.get().update_pyobject(py, data, order)?;
+        Ok(this)
+    }
 
-        let update_closure = || match &self_.threading_mode {
-            ThreadingMode::Single => {
-                self_.rust_hasher.lock().unwrap().update(data_slice);
+    /// Add every buffer from an iterable to the hasher, in order. This is the
+    /// same as calling `update` on each one, but it's faster for iterators
+    /// that produce lots of small chunks, like network reads or generator
+    /// pipelines. Short chunks are copied into a batch and hashed together,
+    /// and long ones are hashed in place with the GIL released.
+    ///
+    /// Arguments:
+    /// - `iterable` (required): Any iterable of buffers.
+    #[pyo3(signature=(iterable, /))]
+    fn update_iter<'py>(
+        this: Bound<'py, Self>,
+        py: Python,
+        iterable: &Bound<PyAny>,
+    ) -> PyResult<Bound<'py, Self>> {
+        let self_ = this.get();
+        let batch_len = default_read_buffer_len(&self_.threading_mode);
+        let mut batch = Vec::with_capacity(batch_len);
+        // The batch is our own copy, so it never needs a snapshot.
+        let flush = |batch: &mut Vec<u8>| {
+            let result = self_.update_inputs(py, &[InputBytes::Slice(batch)], SnapshotMode::Off);
+            batch.clear();
+            result
+        };
+        // Don't hold the lock while the iterator runs. It can run any Python
+        // code, and other threads might be waiting for the lock with the GIL
+        // held.
+        let result = iterable.try_iter()?.try_for_each(|chunk| {
+            // XXX: Get a &[u8] slice of the chunk, or a strided view of it.
+            // The safety situation here is complicated. See all the comments
+            // in bytes_from_pybuffer.
+            let chunk = chunk?;
+            let chunk_buf = BytesPyBuffer::get(&chunk)?;
+            let chunk_bytes = unsafe { chunk_buf.as_input_bytes(Order::C)? };
+            if chunk_bytes.len() >= GIL_MINSIZE {
+                flush(&mut batch)?;
+                return self_.update_inputs(py, &[chunk_bytes], self_.snapshot);
             }
-            ThreadingMode::Auto => {
-                self_.rust_hasher.lock().unwrap().update_rayon(data_slice);
+            if batch.len() + chunk_bytes.len() > batch_len {
+                flush(&mut batch)?;
             }
-            ThreadingMode::Pool { pool, .. } => pool.install(|| {
-                self_.rust_hasher.lock().unwrap().update_rayon(data_slice);
-            }),
-        };
-
-        if data_slice.len() >= GIL_MINSIZE {
-            // Release the GIL while we hash this slice, so that we don't
-            // block other threads. But again, see all the comments above
-            // about data race risks.
-            py.detach(update_closure);
-        } else {
-            // Don't bother releasing the GIL for short updates.
-            update_closure();
-        }
-
+            match &chunk_bytes {
+                InputBytes::Slice(slice) => batch.extend_from_slice(slice),
+                InputBytes::Strided(strided) => {
+                    strided.for_each_piece(batch_len, |piece| batch.extend_from_slice(piece))
+                }
+            }
+            PyResult::Ok(())
+        });
+        // If the iterator raises, keep the chunks that came before the error,
+        // like a loop over `update` would.
+        flush(&mut batch)?;
+        result?;
         Ok(this)
     }
 
-    /// Read a file using memory mapping and add its bytes to the hasher. You can call this any
-    /// number of times.
+    /// Add several buffers to the hasher, in order, like `writev`. This is
+    /// the same as calling `update` on each one, but all the buffers are
+    /// acquired first, and then they're all hashed under one lock and one
+    /// release of the GIL. If any of them isn't a valid buffer, none of them
+    /// are hashed.
     ///
     /// Arguments:
-    /// - `path` (required): The filepath to read.
-    #[pyo3(signature=(path))]
-    fn update_mmap<'py>(
+    /// - `buffers` (required): A list or other iterable of buffers.
+    #[pyo3(signature=(buffers, /))]
+    fn update_many<'py>(
         this: Bound<'py, Self>,
         py: Python,
-        path: PathBuf,
+        buffers: &Bound<PyAny>,
     ) -> PyResult<Bound<'py, Self>> {
         let self_ = this.get();
+        let pybuffers = buffers
+            .try_iter()?
+            .map(|buffer| BytesPyBuffer::get(&buffer?))
+            .collect::<PyResult<Vec<_>>>()?;
 
-        py.detach(|| -> PyResult<()> {
-            match &self_.threading_mode {
-                ThreadingMode::Single => {
-                    self_.rust_hasher.lock().unwrap().update_mmap(&path)?;
-                }
-                ThreadingMode::Auto => {
-                    self_.rust_hasher.lock().unwrap().update_mmap_rayon(&path)?;
-                }
-                ThreadingMode::Pool { pool, .. } => {
-                    pool.install(|| -> PyResult<()> {
-                        self_.rust_hasher.lock().unwrap().update_mmap_rayon(&path)?;
-                        Ok(())
-                    })?;
-                }
-            }
-            Ok(())
-        })?;
+        // XXX: Get &[u8] slices of all the buffers, or strided views of them.
+        // The safety situation here is complicated. See all the comments in
+        // bytes_from_pybuffer.
+        let inputs = pybuffers
+            .iter()
+            .map(|pybuffer| unsafe { pybuffer.as_input_bytes(Order::C) })
+            .collect::<PyResult<Vec<_>>>()?;
+        self_.update_inputs(py, &inputs, self_.snapshot)?;
         Ok(this)
     }
 
-    /// Return a copy (“clone”) of the hasher. This can be used to
-    /// efficiently compute the digests of data sharing a common initial
-    /// substring.
-    #[pyo3(signature=())]
-    fn copy(&self) -> Blake3Class {
-        Blake3Class {
-            rust_hasher: Mutex::new(self.rust_hasher.lock().unwrap().clone()),
+    /// Add the memory of a buffer with any numeric item type to the hasher,
+    /// like an `array.array("I")` or a NumPy `int64` or `float32` array,
+    /// which `update` rejects. Multi-byte items are converted to the given
+    /// byte order first, so that the same numbers give the same hash on
+    /// every platform. Buffers of structs and other compound types aren't
+    /// supported.
+    ///
+    /// Item sizes aren't converted, only byte order. Native C types without
+    /// a fixed size, like `long` (array code "l") and `size_t` ("N"), are
+    /// different sizes on different platforms, so arrays of them hash
+    /// differently too. For portable hashes, use fixed-size types, like
+    /// array code "q" or NumPy's `int64`, or formats with a standard size
+    /// prefix ("<", ">", "!" or "=").
+    ///
+    /// Arguments:
+    /// - `data` (required): The input buffer.
+    /// - `byteorder`: The byte order to hash each item in, either "little"
+    ///   (the default) or "big", like the argument to `int.to_bytes`.
+    /// - `order`: The order to read the items of a multidimensional buffer
+    ///   in. See `update`.
+    #[pyo3(signature=(data, /, *, byteorder="little", order="C"))]
+    fn update_array<'py>(
+        this: Bound<'py, Self>,
+        py: Python,
+        data: &Bound<PyAny>,
+        byteorder: &str,
+        order: &str,
+    ) -> PyResult<Bound<'py, Self>> {
+        let self_ = this.get();
+        let order = Order::from_name(order)?;
+        let big_endian = match byteorder {
+            "little" => false,
+            "big" => true,
+            _ => {
+                return Err(PyValueError::new_err(
+                    "byteorder must be \"little\" or \"big\"",
+                ));
+            }
+        };
+        let pybuffer = PyUntypedBuffer::get(data)?;
+        let swap = strided::format_is_big_endian(pybuffer.format())? != big_endian
+            && pybuffer.item_size() > 1;
+
+        // XXX: Get a &[u8] slice of the data bytes, or a strided view of
+        // them. The safety situation here is complicated. See all the
+        // comments in bytes_from_pybuffer.
+        let data_bytes = unsafe { input_bytes_from_pybuffer(&pybuffer, order, swap)? };
+
+        self_.update_inputs(py, &[data_bytes], self_.snapshot)?;
+        Ok(this)
+    }
+
+    /// Encode a string and add the encoded bytes to the hasher. This is the
+    /// same as `update(s.encode(encoding, errors))`, but it doesn't make an
+    /// encoded copy of the string. UTF-8 and ASCII strings are hashed
+    /// straight from the interpreter's cached UTF-8 representation, and
+    /// UTF-16, UTF-32, and Latin-1 are encoded in Rust a piece at a time,
+    /// with the GIL released for long strings. Other encodings, and strings
+    /// that need the `errors` handler, fall back to `str.encode`.
+    ///
+    /// Arguments:
+    /// - `s` (required): The string to hash.
+    /// - `encoding`: The name of the encoding. Defaults to "utf-8".
+    /// - `errors`: The error handler, as in `str.encode`. Defaults to
+    ///   "strict".
+    #[pyo3(signature=(s, /, encoding="utf-8", errors="strict"))]
+    fn update_str<'py>(
+        this: Bound<'py, Self>,
+        py: Python,
+        s: &Bound<PyString>,
+        encoding: &str,
+        errors: &str,
+    ) -> PyResult<Bound<'py, Self>> {
+        let self_ = this.get();
+        // Normalize the encoding name the same way `str.encode` does, which
+        // also raises LookupError for unknown encodings.
+        let text_encoding = if encoding == "utf-8" {
+            Some(TextEncoding::Utf8 { bom: false })
+        } else {
+            let codec_info = py.import("codecs")?.call_method1("lookup", (encoding,))?;
+            TextEncoding::from_codec_name(codec_info.getattr("name")?.extract()?)
+        };
+
+        // `to_str` fails if the string contains surrogates, which only some
+        // error handlers can encode.
+        if let (Some(text_encoding), Ok(text)) = (text_encoding, s.to_str()) {
+            let buffer_len = default_read_buffer_len(&self_.threading_mode);
+            let update_closure = || {
+                let mut rust_hasher = self_.rust_hasher.lock().unwrap();
+                text_encoding.encode(text, buffer_len, |piece| {
+                    rust_hasher.update(&self_.threading_mode, piece)
+                })
+            };
+            let encoded = if self_.detach_for_update(text_encoding.max_encoded_len(text.len())) {
+                py.detach(update_closure)
+            } else {
+                update_closure()
+            };
+            if encoded {
+                return Ok(this);
+            }
+        }
+
+        // Let Python handle everything else, so that errors and error
+        // handlers work exactly like they do in `str.encode`.
+        let encoded = s.call_method1("encode", (encoding, errors))?;
+        Self::update(this, py, &encoded, "C")
+    }
+
+    /// Read a file using memory mapping and add its bytes to the hasher. You can call this any
+    /// number of times.
+    ///
+    /// Arguments:
+    /// - `path` (required): The filepath to read.
+    /// - `offset`: The byte offset to start reading from. Defaults to 0.
+    /// - `length`: The number of bytes to read. By default, read to the end
+    ///   of the file. If `offset` or `length` is given, only that part of the
+    ///   file is mapped, it must be a regular file, and the range must not go
+    ///   past the end of it.
+    #[pyo3(signature=(path, *, offset=None, length=None))]
+    fn update_mmap<'py>(
+        this: Bound<'py, Self>,
+        py: Python,
+        path: PathBuf,
+        offset: Option<u64>,
+        length: Option<u64>,
+    ) -> PyResult<Bound<'py, Self>> {
+        let self_ = this.get();
+
+        py.detach(|| {
+            let mut rust_hasher = self_.rust_hasher.lock().unwrap();
+            let hasher = rust_hasher.flushed(&self_.threading_mode);
+            self_
+                .threading_mode
+                .update_mmap_range(hasher, &path, offset, length)
+        })?;
+        Ok(this)
+    }
+
+    /// Read a file using positional reads and add its bytes to the hasher.
+    /// This is like `update_mmap`, but it's safe to use on files that other
+    /// processes might truncate. If a memory mapped file gets truncated, the
+    /// whole process can crash with SIGBUS. Here, if the file changes size
+    /// while it's being read, this raises `OSError` instead, and the hasher
+    /// is left unchanged. Multithreaded hashers read several parts of the
+    /// file in parallel.
+    ///
+    /// Arguments:
+    /// - `path` (required): The filepath to read.
+    /// - `offset`: The byte offset to start reading from. Defaults to 0.
+    /// - `length`: The number of bytes to read. By default, read to the end
+    ///   of the file. If `offset` or `length` is given, it must be a regular
+    ///   file, and the range must not go past the end of it.
+    #[pyo3(signature=(path, *, offset=None, length=None))]
+    fn update_path<'py>(
+        this: Bound<'py, Self>,
+        py: Python,
+        path: PathBuf,
+        offset: Option<u64>,
+        length: Option<u64>,
+    ) -> PyResult<Bound<'py, Self>> {
+        let self_ = this.get();
+
+        py.detach(|| {
+            let mut rust_hasher = self_.rust_hasher.lock().unwrap();
+            let hasher = rust_hasher.flushed(&self_.threading_mode);
+            // Hash into a copy, so that an error partway through doesn't
+            // leave the hasher with part of the file in it.
+            let mut new_hasher = hasher.clone();
+            self_
+                .threading_mode
+                .update_path(&mut new_hasher, &path, offset, length)?;
+            *hasher = new_hasher;
+            Ok::<_, PyErr>(())
+        })?;
+        Ok(this)
+    }
+
+    /// Read a binary file object until EOF and add its contents to the
+    /// hasher. This works with any object that has a `readinto` method, like
+    /// `io.BufferedReader`, `io.BytesIO`, sockets opened with `makefile`,
+    /// or `zipfile` members. Each block is hashed with the GIL released. If
+    /// the object is a plain file from `open(..., "rb")` (an `io.FileIO`, or
+    /// an `io.BufferedReader` or `io.BufferedRandom` wrapping one) backed by
+    /// a regular file, the file is memory mapped from the object's current
+    /// position instead, like `update_mmap`, and then the object is seeked
+    /// to the end.
+    ///
+    /// Arguments:
+    /// - `fileobj` (required): The file object to read.
+    /// - `buffer_size`: The size of each read. The default is 64 KiB for
+    ///   single-threaded hashers and 1 MiB otherwise.
+    #[pyo3(signature=(fileobj, /, *, buffer_size=None))]
+    fn update_file<'py>(
+        this: Bound<'py, Self>,
+        py: Python,
+        fileobj: &Bound<PyAny>,
+        buffer_size: Option<usize>,
+    ) -> PyResult<Bound<'py, Self>> {
+        let self_ = this.get();
+        let buffer_len =
+            buffer_size.unwrap_or_else(|| default_read_buffer_len(&self_.threading_mode));
+        // Don't hold the lock while calling readinto. Other threads might be
+        // waiting for it with the GIL held.
+        update_from_fileobj(
+            py,
+            fileobj,
+            buffer_len,
+            |n| self_.detach_for_update(n),
+            |data| {
+                let mut rust_hasher = self_.rust_hasher.lock().unwrap();
+                rust_hasher.update(&self_.threading_mode, data);
+            },
+        )?;
+        Ok(this)
+    }
+
+    /// Read from an OS file descriptor and add the bytes to the hasher. All
+    /// the reading and hashing happens in Rust with the GIL released, so this
+    /// is the fastest way to hash data from a pipe, like the `stdout` of a
+    /// `subprocess.Popen`. Reading stops at EOF, or after `length` bytes. The
+    /// descriptor isn't closed. If a read fails, or a signal handler raises,
+    /// the exception propagates and the hasher is left unchanged, although
+    /// the bytes that were read are gone from the descriptor.
+    ///
+    /// Arguments:
+    /// - `fd` (required): The file descriptor, e.g. from `fileno()`. If it
+    ///   belongs to a Python file object that has already read some data into
+    ///   its own buffer, that data will be skipped.
+    /// - `length`: The maximum number of bytes to read. By default, read
+    ///   until EOF.
+    /// - `offset`: If given, read from this offset with `pread`, which works
+    ///   on regular files but not on pipes. On Unix this doesn't use or
+    ///   change the descriptor's position. By default, read from the current
+    ///   position and advance it.
+    #[pyo3(signature=(fd, /, *, length=None, offset=None))]
+    fn update_fd<'py>(
+        this: Bound<'py, Self>,
+        py: Python,
+        fd: i32,
+        length: Option<u64>,
+        offset: Option<u64>,
+    ) -> PyResult<Bound<'py, Self>> {
+        let self_ = this.get();
+        let file = files::borrow_fd(py, fd)?;
+        let mut buffer = vec![0; default_read_buffer_len(&self_.threading_mode)];
+        let mut remaining = length.unwrap_or(u64::MAX);
+        let mut position = offset;
+        // Hash into a copy, and only replace the hasher at the end, so that an
+        // error partway through doesn't leave the hasher with an unknown part
+        // of the input in it, like in `update_path`. (If another thread
+        // updates this hasher in the meantime, that update is overwritten,
+        // but racing updates like that are already nondeterministic.)
+        let mut new_state = self_.rust_hasher.lock().unwrap().clone();
+        while remaining > 0 {
+            let read_len = usize::try_from(remaining).map_or(buffer.len(), |r| r.min(buffer.len()));
+            // Reads from pipes can block for a long time, so release the GIL
+            // even for short ones. Don't hold the lock while reading, though,
+            // or other threads could end up waiting for it with the GIL held.
+            let result = py.detach(|| -> io::Result<usize> {
+                let read_buf = &mut buffer[..read_len];
+                let n = match position {
+                    Some(position) => files::read_at(&file, read_buf, position)?,
+                    None => io::Read::read(&mut &*file, read_buf)?,
+                };
+                new_state.update(&self_.threading_mode, &read_buf[..n]);
+                Ok(n)
+            });
+            match result {
+                Ok(0) => break,
+                Ok(n) => {
+                    remaining -= n as u64;
+                    if let Some(position) = &mut position {
+                        *position += n as u64;
+                    }
+                }
+                // Like Python's own reads, run signal handlers and retry.
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => py.check_signals()?,
+                Err(e) => return Err(e.into()),
+            }
+        }
+        *self_.rust_hasher.lock().unwrap() = new_state;
+        Ok(this)
+    }
+
+    /// Return a copy (“clone”) of the hasher. This can be used to
+    /// efficiently compute the digests of data sharing a common initial
+    /// substring.
+    #[pyo3(signature=())]
+    fn copy(&self) -> Blake3Class {
+        // Copy the pending bytes too, rather than hashing them with the GIL
+        // held.
+        Blake3Class {
+            rust_hasher: Mutex::new(self.rust_hasher.lock().unwrap().clone()),
             threading_mode: self.threading_mode.clone(),
+            snapshot: self.snapshot,
         }
     }
 
@@ -465,21 +1419,63 @@ impl Blake3Class {
         if length > isize::MAX as usize {
             return Err(PyOverflowError::new_err("length overflows isize"));
         }
-        let mut reader = self.rust_hasher.lock().unwrap().finalize_xof();
-        reader.set_position(seek);
         PyBytes::new_with(py, length, |slice| {
             debug_assert_eq!(length, slice.len());
-            if length >= GIL_MINSIZE {
-                // This could be a long-running operation. Release the GIL.
-                py.detach(|| reader.fill(slice));
-            } else {
-                // Don't bother releasing the GIL for short outputs.
-                reader.fill(slice);
-            }
+            self.fill_output(py, slice, seek);
             Ok(())
         })
     }
 
+    /// Finalize the hasher and write the resulting hash into a writable
+    /// buffer (like a `bytearray`, a `memoryview`, or a NumPy array). The
+    /// length of the buffer determines the length of the output. This does
+    /// not modify the hasher.
+    ///
+    /// Arguments:
+    /// - `buffer` (required): The buffer to fill.
+    /// - `seek`: The starting byte position in the output stream. Defaults
+    ///   to 0.
+    #[pyo3(signature=(buffer, /, *, seek=0))]
+    fn digest_into(&self, py: Python, buffer: &Bound<PyAny>, seek: u64) -> PyResult<()> {
+        // XXX: Get a &mut [u8] slice of the buffer. The safety situation here
+        // is complicated. See all the comments in bytes_from_pybuffer.
+        let buf = BytesPyBuffer::get_writable(buffer)?;
+        let slice: &mut [u8] = unsafe { buf.as_bytes_mut()? };
+        self.fill_output(py, slice, seek);
+        Ok(())
+    }
+
+    /// Finalize the hasher and write the resulting hash into a writable
+    /// buffer as lowercase hexadecimal ASCII characters. The buffer length
+    /// must be even, and half of it determines the length of the output.
+    /// This does not modify the hasher.
+    ///
+    /// Arguments:
+    /// - `buffer` (required): The buffer to fill.
+    /// - `seek`: The starting byte position in the output stream, prior to
+    ///   hex encoding. Defaults to 0.
+    #[pyo3(signature=(buffer, /, *, seek=0))]
+    fn hexdigest_into(&self, py: Python, buffer: &Bound<PyAny>, seek: u64) -> PyResult<()> {
+        // XXX: Get a &mut [u8] slice of the buffer. The safety situation here
+        // is complicated. See all the comments in bytes_from_pybuffer.
+        let buf = BytesPyBuffer::get_writable(buffer)?;
+        let slice: &mut [u8] = unsafe { buf.as_bytes_mut()? };
+        if slice.len() & 1 != 0 {
+            let msg = format!("expected a buffer of even length, found {}", slice.len());
+            return Err(PyValueError::new_err(msg));
+        }
+        // Write the raw output into the first half of the buffer, and then
+        // expand it in place.
+        let half = slice.len() / 2;
+        self.fill_output(py, &mut slice[..half], seek);
+        if slice.len() >= GIL_MINSIZE {
+            py.detach(|| hex_encode_in_place(slice));
+        } else {
+            hex_encode_in_place(slice);
+        }
+        Ok(())
+    }
+
     /// Finalize the hasher and return the resulting hash as a hexadecimal
     /// string. This does not modify the hasher, and calling it twice will
     /// give the same result. You can also add more input and finalize
@@ -505,15 +1501,1783 @@ impl Blake3Class {
         let hex = hex::encode(bytes.as_bytes());
         Ok(PyString::new(py, &hex))
     }
+
+    /// Finalize the hasher and check that the output matches `expected`,
+    /// using a constant-time comparison. The length of `expected` determines
+    /// the length of the output. Raises `blake3.VerificationError` (a
+    /// subclass of `ValueError`) if they don't match. This does not modify
+    /// the hasher.
+    ///
+    /// Arguments:
+    /// - `expected` (required): The expected output bytes. This can't be
+    ///   empty, because an empty output would match anything.
+    /// - `seek`: The starting byte position in the output stream. Defaults
+    ///   to 0.
+    #[pyo3(signature=(expected, /, *, seek=0))]
+    fn verify(&self, py: Python, expected: &Bound<PyAny>, seek: u64) -> PyResult<()> {
+        // XXX: Get a &[u8] slice of the expected bytes. The safety situation
+        // here is complicated. See all the comments in bytes_from_pybuffer.
+        let expected_buf = BytesPyBuffer::get(expected)?;
+        let expected_slice: &[u8] = unsafe { expected_buf.as_bytes()? };
+        if expected_slice.is_empty() {
+            return Err(PyValueError::new_err("expected output is empty"));
+        }
+        let mut output = vec![0; expected_slice.len()];
+        self.fill_output(py, &mut output, seek);
+        if constant_time_eq::constant_time_eq(&output, expected_slice) {
+            Ok(())
+        } else {
+            Err(VerificationError::new_err("output does not match"))
+        }
+    }
+
+    /// Finalize the hasher and return the resulting 32-byte hash as a
+    /// `Hash` object, which compares equal to other hashes in constant time.
+    /// This does not modify the hasher, and calling it twice will give the
+    /// same result. You can also add more input and finalize again.
+    #[pyo3(signature=())]
+    fn finalize(&self, py: Python) -> HashClass {
+        HashClass {
+            rust_hash: self.with_flushed(py, |hasher| hasher.finalize()),
+        }
+    }
+
+    /// Finalize the hasher and return the resulting hash as a string in
+    /// one of several text encodings. This does not modify the hasher.
+    ///
+    /// Arguments:
+    /// - `encoding` (required): One of "hex", "base32" (RFC 4648, without
+    ///   padding), "base32crockford" (without check symbols), "base58" (the
+    ///   Bitcoin alphabet, which takes quadratic time, so it only supports
+    ///   lengths up to 1024), "base64" (RFC 4648, with padding), "base64url"
+    ///   (RFC 4648, without padding), or "z85" (which requires a length
+    ///   that's a multiple of 4).
+    /// - `length`: The number of bytes in the final hash, prior to
+    ///   encoding. Defaults to 32.
+    /// - `seek`: The starting byte position in the output stream, prior to
+    ///   encoding. Defaults to 0.
+    /// - `uppercase`: Use uppercase letters. Only supported for "hex",
+    ///   "base32" and "base32crockford". Defaults to False.
+    /// - `sep`: A separator to insert between bytes, like `bytes.hex`. Only
+    ///   supported for "hex". Defaults to None.
+    #[pyo3(signature=(encoding, length=32, *, seek=0, uppercase=false, sep=None))]
+    fn encoded_digest<'p>(
+        &self,
+        py: Python<'p>,
+        encoding: &str,
+        length: usize,
+        seek: u64,
+        uppercase: bool,
+        sep: Option<&str>,
+    ) -> PyResult<Bound<'p, PyString>> {
+        let encoding = Encoding::from_name(encoding)?;
+        if length > (isize::MAX / 2) as usize {
+            return Err(PyOverflowError::new_err("length overflows isize"));
+        }
+        encoding.check_len(length)?;
+        let output = self.digest(py, length, seek)?;
+        let output = output.as_bytes();
+        let encoded = if length >= GIL_MINSIZE {
+            py.detach(|| encoding.encode(output, uppercase, sep))?
+        } else {
+            encoding.encode(output, uppercase, sep)?
+        };
+        new_pystring(py, &encoded)
+    }
+
+    /// Finalize the hasher and return the resulting hash as a multihash,
+    /// i.e. the BLAKE3 multihash code (0x1e) and the digest length as
+    /// varints, followed by the digest bytes. This does not modify the
+    /// hasher.
+    ///
+    /// Arguments:
+    /// - `length`: The number of bytes in the digest. This must be at least
+    ///   1. Defaults to 32.
+    /// - `seek`: The starting byte position in the output stream. Defaults
+    ///   to 0.
+    #[pyo3(signature=(length=32, *, seek=0))]
+    fn multihash<'p>(
+        &self,
+        py: Python<'p>,
+        length: usize,
+        seek: u64,
+    ) -> PyResult<Bound<'p, PyBytes>> {
+        let digest = self.digest(py, length, seek)?;
+        let framed = multiformats::multihash(digest.as_bytes())?;
+        Ok(PyBytes::new(py, &framed))
+    }
+
+    /// Finalize the hasher and return the resulting hash as a CIDv1
+    /// string, for IPFS-style content addressing. This does not modify the
+    /// hasher.
+    ///
+    /// Arguments:
+    /// - `codec`: The multicodec of the content, either as an integer or as
+    ///   one of the names "raw", "dag-pb", "dag-cbor", "dag-json", "cbor",
+    ///   or "json". Defaults to "raw".
+    /// - `base`: The multibase encoding of the CID, one of "base32",
+    ///   "base32upper", "base58btc", "base16", "base16upper", or
+    ///   "base64url". Defaults to "base32", like IPFS.
+    /// - `length`: The number of bytes in the digest. This must be at least
+    ///   1. Defaults to 32.
+    /// - `seek`: The starting byte position in the output stream. Defaults
+    ///   to 0.
+    #[pyo3(signature=(codec=None, base="base32", *, length=32, seek=0))]
+    fn cid(
+        &self,
+        py: Python,
+        codec: Option<&Bound<PyAny>>,
+        base: &str,
+        length: usize,
+        seek: u64,
+    ) -> PyResult<String> {
+        let codec = match codec {
+            Some(codec) => multiformats::codec_from_py(codec)?,
+            None => multiformats::RAW_CODEC,
+        };
+        let digest = self.digest(py, length, seek)?;
+        multiformats::cid(digest.as_bytes(), codec, base)
+    }
+
+    /// Finalize the hasher and return an `OutputReader`, which can read
+    /// any number of output bytes incrementally. This does not modify the
+    /// hasher, and calling it twice will give the same result. You can also
+    /// add more input and finalize again.
+    #[pyo3(signature=())]
+    fn finalize_xof(&self, py: Python) -> OutputReaderClass {
+        OutputReaderClass {
+            rust_reader: Mutex::new(Some(self.with_flushed(py, |hasher| hasher.finalize_xof()))),
+        }
+    }
+}
+
+/// A 32-byte BLAKE3 hash, returned by `blake3.finalize`. Comparing a `Hash`
+/// with `==` is constant-time, so it's safe to use for checking MACs. A
+/// `Hash` can be compared to another `Hash` or to any buffer (like `bytes`)
+/// of the same length, which makes it a drop-in replacement for the output
+/// of `digest`.
+///
+/// Arguments:
+/// - `data` (required): The 32 hash bytes.
+#[pyclass(name = "Hash", module = "blake3.blake3", frozen)]
+struct HashClass {
+    rust_hash: upstream_blake3::Hash,
+}
+
+#[pymethods]
+impl HashClass {
+    #[new]
+    #[pyo3(signature=(data, /))]
+    fn new(data: &Bound<PyAny>) -> PyResult<HashClass> {
+        // Use the same helper as `update`. Even though we copy the bytes
+        // immediately here, technically this risks the same race conditions.
+        let data_buf = BytesPyBuffer::get(data)?;
+        let data_slice: &[u8] = unsafe { data_buf.as_bytes()? };
+        let data_array: [u8; 32] = match data_slice.try_into() {
+            Ok(array) => array,
+            Err(_) => {
+                let msg = format!("expected {} bytes, found {}", 32, data_slice.len());
+                return Err(PyValueError::new_err(msg));
+            }
+        };
+        Ok(HashClass {
+            rust_hash: data_array.into(),
+        })
+    }
+
+    /// Parse a `Hash` from a string of 64 hexadecimal characters, either
+    /// uppercase or lowercase.
+    ///
+    /// Arguments:
+    /// - `hex` (required): The hex string.
+    #[staticmethod]
+    #[pyo3(signature=(hex, /))]
+    fn from_hex(hex: &str) -> PyResult<HashClass> {
+        match upstream_blake3::Hash::from_hex(hex) {
+            Ok(rust_hash) => Ok(HashClass { rust_hash }),
+            Err(e) => Err(PyValueError::new_err(e.to_string())),
+        }
+    }
+
+    /// Return the hash as a string of 64 lowercase hexadecimal characters.
+    #[pyo3(signature=())]
+    fn to_hex(&self) -> String {
+        self.rust_hash.to_hex().to_string()
+    }
+
+    fn __bytes__<'p>(&self, py: Python<'p>) -> Bound<'p, PyBytes> {
+        PyBytes::new(py, self.rust_hash.as_bytes())
+    }
+
+    fn __eq__(&self, py: Python, other: &Bound<PyAny>) -> PyResult<Py<PyAny>> {
+        // These comparisons are constant-time in the upstream crate.
+        let equal = if let Ok(other_hash) = other.cast::<HashClass>() {
+            self.rust_hash == other_hash.get().rust_hash
+        } else if let Ok(other_buf) = BytesPyBuffer::get(other) {
+            // XXX: See all the comments in bytes_from_pybuffer.
+            match unsafe { other_buf.as_bytes() } {
+                Ok(other_bytes) => self.rust_hash == *other_bytes,
+                // Comparisons shouldn't raise, so treat buffers we can't read
+                // as contiguous bytes like any other unsupported type.
+                Err(_) => return Ok(py.NotImplemented()),
+            }
+        } else {
+            return Ok(py.NotImplemented());
+        };
+        Ok(PyBool::new(py, equal).to_owned().into_any().unbind())
+    }
+
+    fn __hash__(&self, py: Python) -> PyResult<isize> {
+        // Equal objects must have equal hashes, and a `Hash` is equal to the
+        // `bytes` it contains, so use the `bytes` hash.
+        PyBytes::new(py, self.rust_hash.as_bytes()).hash()
+    }
+
+    fn __str__(&self) -> String {
+        self.to_hex()
+    }
+
+    fn __repr__(&self) -> String {
+        format!("blake3.Hash.from_hex('{}')", self.rust_hash.to_hex())
+    }
+
+    fn __reduce__<'p>(this: &Bound<'p, Self>) -> (Bound<'p, PyType>, (Bound<'p, PyBytes>,)) {
+        (this.get_type(), (this.get().__bytes__(this.py()),))
+    }
+}
+
+/// An incremental reader for the extended output of a BLAKE3 hasher,
+/// returned by `blake3.finalize_xof`. The output stream is effectively
+/// infinite (2**64 bytes), and it's seekable. This class implements the
+/// same interface as `io.RawIOBase`, and it's registered as a virtual
+/// subclass of it, so it can be wrapped in e.g. `io.BufferedReader`.
+// The reader is wrapped in a Mutex for the same reasons as the hasher above.
+// None means the reader has been closed.
+#[pyclass(name = "OutputReader", module = "blake3.blake3", frozen)]
+struct OutputReaderClass {
+    rust_reader: Mutex<Option<upstream_blake3::OutputReader>>,
+}
+
+impl OutputReaderClass {
+    /// Run a closure against the inner reader, or raise ValueError if the
+    /// reader is closed. This is the same error that `io` classes raise.
+    fn with_reader<T>(
+        &self,
+        f: impl FnOnce(&mut upstream_blake3::OutputReader) -> PyResult<T>,
+    ) -> PyResult<T> {
+        match self.rust_reader.lock().unwrap().as_mut() {
+            Some(reader) => f(reader),
+            None => Err(PyValueError::new_err("I/O operation on closed reader")),
+        }
+    }
+
+    /// Claim the next `len` bytes of output, and return a copy of the inner
+    /// reader positioned at the start of them. The lock is released before
+    /// the caller fills its output, so we don't hold it while the GIL is
+    /// released, and concurrent reads get separate parts of the stream.
+    fn claim(&self, len: usize) -> PyResult<upstream_blake3::OutputReader> {
+        self.with_reader(|reader| {
+            let claimed = reader.clone();
+            reader.set_position(reader.position().wrapping_add(len as u64));
+            Ok(claimed)
+        })
+    }
+}
+
+#[pymethods]
+impl OutputReaderClass {
+    /// Read `size` output bytes from the current position, and advance the
+    /// position by that many bytes. Unlike most readers, this can't read to
+    /// the end, because the output stream has no end.
+    ///
+    /// Arguments:
+    /// - `size`: The number of bytes to read. This defaults to -1, like in
+    ///   other readers, but -1 (or any negative size) raises
+    ///   `io.UnsupportedOperation`, so in practice it's required.
+    #[pyo3(signature=(size=-1, /))]
+    fn read<'p>(&self, py: Python<'p>, size: isize) -> PyResult<Bound<'p, PyBytes>> {
+        if size < 0 {
+            return Err(UnsupportedOperation::new_err(
+                "the output stream is unbounded, so read() requires a size",
+            ));
+        }
+        let size = size as usize;
+        // Allocate before claiming the output, so that if the allocation
+        // fails, the position doesn't move.
+        PyBytes::new_with(py, size, |slice| {
+            let mut reader = self.claim(size)?;
+            if size >= GIL_MINSIZE {
+                // This could be a long-running operation. Release the GIL.
+                py.detach(|| reader.fill(slice));
+            } else {
+                // Don't bother releasing the GIL for short outputs.
+                reader.fill(slice);
+            }
+            Ok(())
+        })
+    }
+
+    /// Fill a writable buffer (like a `bytearray` or a `memoryview`) with
+    /// output bytes from the current position, and advance the position by
+    /// the length of the buffer. Returns the number of bytes written, which
+    /// is always the length of the buffer.
+    ///
+    /// Arguments:
+    /// - `buffer` (required): The buffer to fill.
+    #[pyo3(signature=(buffer, /))]
+    fn readinto(&self, py: Python, buffer: &Bound<PyAny>) -> PyResult<usize> {
+        // XXX: Get a &mut [u8] slice of the buffer. The safety situation here
+        // is complicated. See all the comments in bytes_from_pybuffer.
+        let buf = BytesPyBuffer::get_writable(buffer)?;
+        let slice: &mut [u8] = unsafe { buf.as_bytes_mut()? };
+        let len = slice.len();
+        let mut reader = self.claim(len)?;
+        if len >= GIL_MINSIZE {
+            // This could be a long-running operation. Release the GIL.
+            py.detach(|| reader.fill(slice));
+        } else {
+            // Don't bother releasing the GIL for short outputs.
+            reader.fill(slice);
+        }
+        Ok(len)
+    }
+
+    /// Not supported. The output stream has no end. This always raises
+    /// `io.UnsupportedOperation`.
+    #[pyo3(signature=())]
+    fn readall(&self) -> PyResult<()> {
+        Err(UnsupportedOperation::new_err(
+            "the output stream is unbounded, so it can't be read in full",
+        ))
+    }
+
+    /// Read up to and including the next newline byte, or until `size` bytes
+    /// have been read. Without a `size`, this can read any number of bytes,
+    /// but in practice lines are a few hundred bytes long.
+    ///
+    /// Arguments:
+    /// - `size`: The maximum number of bytes to read. The default (-1) means
+    ///   no limit.
+    #[pyo3(signature=(size=-1, /))]
+    fn readline<'p>(&self, py: Python<'p>, size: isize) -> PyResult<Bound<'p, PyBytes>> {
+        let limit = usize::try_from(size).unwrap_or(usize::MAX);
+        let line = self.with_reader(|reader| {
+            let mut line = Vec::new();
+            let mut block = [0; 64];
+            while line.len() < limit {
+                let start = reader.position();
+                let block = &mut block[..(limit - line.len()).min(64)];
+                reader.fill(block);
+                if let Some(newline) = block.iter().position(|&b| b == b'\n') {
+                    line.extend_from_slice(&block[..=newline]);
+                    reader.set_position(start.wrapping_add(newline as u64 + 1));
+                    break;
+                }
+                line.extend_from_slice(block);
+            }
+            Ok(line)
+        })?;
+        Ok(PyBytes::new(py, &line))
+    }
+
+    /// Read lines until their total length reaches `hint`, and return them
+    /// as a list. Unlike most readers, this can't read every line, because
+    /// the output stream has no end.
+    ///
+    /// Arguments:
+    /// - `hint`: The total length to stop reading at. This defaults to -1,
+    ///   like in other readers, but -1 (or any hint that isn't positive)
+    ///   raises `io.UnsupportedOperation`, so in practice it's required.
+    #[pyo3(signature=(hint=-1, /))]
+    fn readlines<'p>(&self, py: Python<'p>, hint: isize) -> PyResult<Vec<Bound<'p, PyBytes>>> {
+        if hint <= 0 {
+            return Err(UnsupportedOperation::new_err(
+                "the output stream is unbounded, so readlines() requires a hint",
+            ));
+        }
+        let mut lines = Vec::new();
+        let mut total = 0;
+        while total < hint as usize {
+            let line = self.readline(py, -1)?;
+            total += line.as_bytes().len();
+            lines.push(line);
+        }
+        Ok(lines)
+    }
+
+    fn __iter__(this: Bound<Self>) -> PyResult<Bound<Self>> {
+        this.get().with_reader(|_| Ok(()))?;
+        Ok(this)
+    }
+
+    /// Iterating over the reader yields lines, like `readline`, forever.
+    fn __next__<'p>(&self, py: Python<'p>) -> PyResult<Bound<'p, PyBytes>> {
+        self.readline(py, -1)
+    }
+
+    /// Change the current position of the reader, and return the new
+    /// position.
+    ///
+    /// Arguments:
+    /// - `offset` (required): The target position, relative to `whence`.
+    /// - `whence`: `os.SEEK_SET` (0, the default) to seek relative to the
+    ///   start of the output stream, or `os.SEEK_CUR` (1) to seek relative to
+    ///   the current position. `os.SEEK_END` is not supported.
+    #[pyo3(signature=(offset, whence=0, /))]
+    fn seek(&self, offset: i128, whence: i32) -> PyResult<u64> {
+        self.with_reader(|reader| {
+            let base = match whence {
+                0 => 0,
+                1 => reader.position() as i128,
+                2 => {
+                    return Err(UnsupportedOperation::new_err(
+                        "the output stream has no end to seek relative to",
+                    ));
+                }
+                _ => return Err(PyValueError::new_err(format!("invalid whence ({whence})"))),
+            };
+            let position = match u64::try_from(base + offset) {
+                Ok(position) => position,
+                Err(_) => {
+                    return Err(PyValueError::new_err(
+                        "position must be between 0 and 2**64-1",
+                    ));
+                }
+            };
+            reader.set_position(position);
+            Ok(position)
+        })
+    }
+
+    /// Return the current position of the reader.
+    #[pyo3(signature=())]
+    fn tell(&self) -> PyResult<u64> {
+        self.with_reader(|reader| Ok(reader.position()))
+    }
+
+    /// Always true.
+    #[pyo3(signature=())]
+    fn readable(&self) -> PyResult<bool> {
+        self.with_reader(|_| Ok(true))
+    }
+
+    /// Always true.
+    #[pyo3(signature=())]
+    fn seekable(&self) -> PyResult<bool> {
+        self.with_reader(|_| Ok(true))
+    }
+
+    /// Always false.
+    #[pyo3(signature=())]
+    fn writable(&self) -> PyResult<bool> {
+        self.with_reader(|_| Ok(false))
+    }
+
+    /// Always false.
+    #[pyo3(signature=())]
+    fn isatty(&self) -> PyResult<bool> {
+        self.with_reader(|_| Ok(false))
+    }
+
+    /// Does nothing, because the reader isn't writable.
+    #[pyo3(signature=())]
+    fn flush(&self) -> PyResult<()> {
+        self.with_reader(|_| Ok(()))
+    }
+
+    /// Not supported. The reader isn't backed by a file descriptor. This
+    /// always raises `io.UnsupportedOperation`.
+    #[pyo3(signature=())]
+    fn fileno(&self) -> PyResult<i32> {
+        Err(UnsupportedOperation::new_err(
+            "the output reader has no file descriptor",
+        ))
+    }
+
+    /// Close the reader. Any further operations (other than `close`) raise
+    /// `ValueError`.
+    #[pyo3(signature=())]
+    fn close(&self) {
+        *self.rust_reader.lock().unwrap() = None;
+    }
+
+    /// True if the reader has been closed.
+    #[getter]
+    fn closed(&self) -> bool {
+        self.rust_reader.lock().unwrap().is_none()
+    }
+
+    fn __enter__(this: Bound<Self>) -> PyResult<Bound<Self>> {
+        this.get().with_reader(|_| Ok(()))?;
+        Ok(this)
+    }
+
+    #[pyo3(signature=(*_args))]
+    fn __exit__(&self, _args: &Bound<PyTuple>) {
+        self.close();
+    }
+}
+
+/// What `hash_files` does when it can't read a file.
+#[derive(Clone, Copy)]
+enum OnError {
+    Raise,
+    Skip,
+    Return,
+}
+
+impl OnError {
+    fn from_name(name: &str) -> PyResult<Self> {
+        match name {
+            "raise" => Ok(Self::Raise),
+            "skip" => Ok(Self::Skip),
+            "return" => Ok(Self::Return),
+            _ => Err(PyValueError::new_err(format!(
+                "unknown on_error {name:?}, expected one of: raise, skip, return"
+            ))),
+        }
+    }
+}
+
+/// A file path paired with its hash, or with the error from reading it.
+type FileResult = (PathBuf, io::Result<Vec<u8>>);
+
+/// The shared state for hashing many files in `hash_files`, `checksum_lines`
+/// and `check`.
+struct FilesHasher {
+    template: upstream_blake3::Hasher,
+    threading_mode: ThreadingMode,
+    length: usize,
+}
+
+impl FilesHasher {
+    fn new(
+        template: upstream_blake3::Hasher,
+        threading_mode: ThreadingMode,
+        length: usize,
+    ) -> PyResult<Self> {
+        if length > isize::MAX as usize {
+            return Err(PyOverflowError::new_err("length overflows isize"));
+        }
+        Ok(Self {
+            template,
+            threading_mode,
+            length,
+        })
+    }
+
+    fn hash(&self, item: files::WalkItem) -> FileResult {
+        let path = match item {
+            Ok(path) => path,
+            Err((path, e)) => return (path, Err(e)),
+        };
+        // Allocate the output first, so we don't read the file if we can't.
+        // The callers raise `MemoryError` for this error, even when they
+        // skip or return other errors.
+        let mut output = Vec::new();
+        if let Err(e) = output.try_reserve_exact(self.length) {
+            return (path, Err(io::Error::new(io::ErrorKind::OutOfMemory, e)));
+        }
+        output.resize(self.length, 0);
+        let mut hasher = self.template.clone();
+        if let Err(e) = self.threading_mode.update_mmap(&mut hasher, &path) {
+            return (path, Err(e));
+        }
+        hasher.finalize_xof().fill(&mut output);
+        (path, Ok(output))
+    }
+
+    /// Hash all the files from a walk or a list of paths, in parallel if the
+    /// threading mode allows it, and return the results in order.
+    fn hash_in_order(&self, items: impl IntoIterator<Item = files::WalkItem>) -> Vec<FileResult> {
+        let items: Vec<files::WalkItem> = items.into_iter().collect();
+        match &self.threading_mode {
+            ThreadingMode::Single => items.into_iter().map(|item| self.hash(item)).collect(),
+            ThreadingMode::Auto => items.into_par_iter().map(|item| self.hash(item)).collect(),
+            ThreadingMode::Pool { pool, .. } => {
+                pool.install(|| items.into_par_iter().map(|item| self.hash(item)).collect())
+            }
+        }
+    }
+
+    /// Like `hash_in_order`, but start hashing while the walk is still
+    /// going, and send each result as soon as it's ready. Stop early if the
+    /// receiver goes away.
+    fn hash_to_channel(&self, mut walk: files::Walk, sender: mpsc::SyncSender<FileResult>) {
+        let send_one = |item| sender.send(self.hash(item));
+        // The only possible error is a disconnected receiver.
+        let _ = match &self.threading_mode {
+            ThreadingMode::Single => walk.try_for_each(send_one),
+            ThreadingMode::Auto => walk.par_bridge().try_for_each(send_one),
+            ThreadingMode::Pool { pool, .. } => {
+                pool.install(|| walk.par_bridge().try_for_each(send_one))
+            }
+        };
+    }
+}
+
+/// Convert one result from `hash_files` into a Python `(path, digest)` pair,
+/// or `None` to skip it.
+fn file_result_to_py<'p>(
+    py: Python<'p>,
+    (path, result): FileResult,
+    on_error: OnError,
+) -> PyResult<Option<(Bound<'p, PyAny>, Bound<'p, PyAny>)>> {
+    let value = match (result, on_error) {
+        (Ok(digest), _) => PyBytes::new(py, &digest).into_any(),
+        // This is `MemoryError`, which isn't about the file.
+        (Err(e), _) if e.kind() == io::ErrorKind::OutOfMemory => return Err(e.into()),
+        (Err(e), OnError::Raise) => return Err(files::os_error(&path, e)),
+        (Err(_), OnError::Skip) => return Ok(None),
+        (Err(e), OnError::Return) => files::os_error(&path, e)
+            .into_value(py)
+            .into_bound(py)
+            .into_any(),
+    };
+    Ok(Some((path.into_pyobject(py)?, value)))
+}
+
+/// An iterator over `(path, digest)` pairs, returned by `hash_files` with
+/// `stream=True`. Files are hashed in the background, and results come out
+/// in the order they finish.
+#[pyclass(name = "HashFilesIterator", module = "blake3.blake3", frozen)]
+struct HashFilesIterator {
+    receiver: Mutex<mpsc::Receiver<FileResult>>,
+    on_error: OnError,
+}
+
+#[pymethods]
+impl HashFilesIterator {
+    fn __iter__(this: Bound<Self>) -> Bound<Self> {
+        this
+    }
+
+    fn __next__<'p>(
+        &self,
+        py: Python<'p>,
+    ) -> PyResult<Option<(Bound<'p, PyAny>, Bound<'p, PyAny>)>> {
+        loop {
+            // Block with the GIL released until the next result is ready.
+            // An error here means the background thread is done.
+            let Ok(file_result) = py.detach(|| self.receiver.lock().unwrap().recv()) else {
+                return Ok(None);
+            };
+            if let Some(pair) = file_result_to_py(py, file_result, self.on_error)? {
+                return Ok(Some(pair));
+            }
+        }
+    }
+}
+
+/// The result of checking one file with `blake3.check`.
+///
+/// Attributes:
+/// - `path`: The path from the checksum file, as a string.
+/// - `status`: "OK" if the file's hash matched, "FAILED" if it didn't,
+///   "MISSING" if the file doesn't exist, or "ERROR" if it couldn't be read.
+/// - `error`: The `OSError` for "MISSING" and "ERROR", otherwise None.
+#[pyclass(name = "CheckResult", module = "blake3.blake3", frozen, get_all)]
+struct CheckResult {
+    path: String,
+    status: &'static str,
+    error: Option<Py<PyAny>>,
+}
+
+#[pymethods]
+impl CheckResult {
+    /// True if the status is "OK".
+    #[getter]
+    fn ok(&self) -> bool {
+        self.status == "OK"
+    }
+
+    fn __repr__(&self, py: Python) -> PyResult<String> {
+        Ok(format!(
+            "blake3.CheckResult(path={}, status='{}')",
+            PyString::new(py, &self.path).repr()?,
+            self.status,
+        ))
+    }
+}
+
+/// Set the default `snapshot` mode for new `blake3` hashers. By default,
+/// hashers read input buffers in place with the GIL released, which means
+/// another thread writing to a buffer at the same time can race with hashing
+/// it. That's almost certainly a bug in the caller, but on free-threaded
+/// builds the results aren't even well defined. Setting this to True makes
+/// hashers copy their inputs first, and "detect" also makes them raise
+/// `BufferError` if a buffer changed while it was being hashed. This doesn't
+/// affect hashers that already exist, or the one-shot functions like `hash`.
+///
+/// Arguments:
+/// - `snapshot` (required): True, False, or "detect".
+#[pyfunction]
+#[pyo3(signature=(snapshot, /))]
+fn set_snapshot_default(snapshot: &Bound<PyAny>) -> PyResult<()> {
+    let snapshot = SnapshotMode::from_py(snapshot)?;
+    SNAPSHOT_DEFAULT.store(snapshot as u8, Ordering::Relaxed);
+    Ok(())
+}
+
+/// Return the default `snapshot` mode for new `blake3` hashers: True, False,
+/// or "detect". See `set_snapshot_default`.
+#[pyfunction]
+fn get_snapshot_default(py: Python) -> Bound<PyAny> {
+    SnapshotMode::default().into_py(py)
+}
+
+/// The shared implementation of `hash`, `keyed_hash` and `derive_key`. The
+/// common case, a 32-byte output from a single thread, goes directly to the
+/// upstream function for that mode, which avoids some of the overhead of an
+/// incremental `Hasher`.
+fn hash_one_shot<'p>(
+    py: Python<'p>,
+    data: &Bound<PyAny>,
+    length: usize,
+    max_threads: isize,
+    new_hasher: impl FnOnce() -> upstream_blake3::Hasher + Send,
+    hash_32: impl FnOnce(&[u8]) -> [u8; 32] + Send,
+) -> PyResult<Bound<'p, PyBytes>> {
+    if length > isize::MAX as usize {
+        return Err(PyOverflowError::new_err("length overflows isize"));
+    }
+    let threading_mode = ThreadingMode::new(max_threads)?;
+
+    // XXX: Get a &[u8] slice of the data bytes. The safety situation here is
+    // complicated. See all the comments in bytes_from_pybuffer.
+    let data_buf = BytesPyBuffer::get(data)?;
+    let data_slice: &[u8] = unsafe { data_buf.as_bytes()? };
+    let release_gil = data_slice.len() >= GIL_MINSIZE;
+
+    if length == 32 && matches!(threading_mode, ThreadingMode::Single) {
+        let hash_closure = || hash_32(data_slice);
+        let hash = if release_gil {
+            py.detach(hash_closure)
+        } else {
+            hash_closure()
+        };
+        return Ok(PyBytes::new(py, &hash));
+    }
+
+    let hash_closure = || {
+        let mut hasher = new_hasher();
+        threading_mode.update(&mut hasher, data_slice);
+        hasher.finalize_xof()
+    };
+    let mut reader = if release_gil {
+        py.detach(hash_closure)
+    } else {
+        hash_closure()
+    };
+    PyBytes::new_with(py, length, |slice| {
+        fill_output(py, &threading_mode, &mut reader, slice);
+        Ok(())
+    })
+}
+
+/// Hash some input all at once and return the hash as bytes. This is
+/// equivalent to `blake3(data, max_threads=max_threads).digest(length)`,
+/// but with less overhead for short inputs.
+///
+/// Arguments:
+/// - `data` (required): The input bytes.
+/// - `length`: The number of bytes in the final hash. Defaults to 32.
+/// - `max_threads`: The maximum number of threads that the implementation
+///   may use for hashing. See the `blake3` class. Defaults to 1.
+#[pyfunction]
+#[pyo3(signature=(data, /, length=32, *, max_threads=1))]
+fn hash<'p>(
+    py: Python<'p>,
+    data: &Bound<PyAny>,
+    length: usize,
+    max_threads: isize,
+) -> PyResult<Bound<'p, PyBytes>> {
+    hash_one_shot(
+        py,
+        data,
+        length,
+        max_threads,
+        upstream_blake3::Hasher::new,
+        |input| upstream_blake3::hash(input).into(),
+    )
+}
+
+/// Hash some input all at once with the BLAKE3 keyed hashing mode, and
+/// return the hash as bytes. This is equivalent to
+/// `blake3(data, key=key, max_threads=max_threads).digest(length)`, but with
+/// less overhead for short inputs.
+///
+/// Arguments:
+/// - `key` (required): A 32-byte key.
+/// - `data` (required): The input bytes.
+/// - `length`: The number of bytes in the final hash. Defaults to 32.
+/// - `max_threads`: The maximum number of threads that the implementation
+///   may use for hashing. See the `blake3` class. Defaults to 1.
+#[pyfunction]
+#[pyo3(signature=(key, data, /, length=32, *, max_threads=1))]
+fn keyed_hash<'p>(
+    py: Python<'p>,
+    key: &Bound<PyAny>,
+    data: &Bound<PyAny>,
+    length: usize,
+    max_threads: isize,
+) -> PyResult<Bound<'p, PyBytes>> {
+    let key_array = key_from_pyobject(key)?;
+    hash_one_shot(
+        py,
+        data,
+        length,
+        max_threads,
+        || upstream_blake3::Hasher::new_keyed(&key_array),
+        |input| upstream_blake3::keyed_hash(&key_array, input).into(),
+    )
+}
+
+/// Derive a key all at once with the BLAKE3 key derivation mode, and return
+/// it as bytes. This is equivalent to
+/// `blake3(key_material, derive_key_context=context, max_threads=max_threads).digest(length)`,
+/// but with less overhead.
+///
+/// Arguments:
+/// - `context` (required): A hardcoded, globally unique,
+///   application-specific context string.
+/// - `key_material` (required): The input key material.
+/// - `length`: The number of bytes in the derived key. Defaults to 32.
+/// - `max_threads`: The maximum number of threads that the implementation
+///   may use for hashing. See the `blake3` class. Defaults to 1.
+#[pyfunction]
+#[pyo3(signature=(context, key_material, /, length=32, *, max_threads=1))]
+fn derive_key<'p>(
+    py: Python<'p>,
+    context: &str,
+    key_material: &Bound<PyAny>,
+    length: usize,
+    max_threads: isize,
+) -> PyResult<Bound<'p, PyBytes>> {
+    hash_one_shot(
+        py,
+        key_material,
+        length,
+        max_threads,
+        || upstream_blake3::Hasher::new_derive_key(context),
+        |input| upstream_blake3::derive_key(context, input),
+    )
+}
+
+/// Hash many independent inputs, in parallel by default, and return their
+/// hashes. This is equivalent to calling `blake3(x).digest(length)` for
+/// each input `x`, but all the buffers are acquired up front and all the
+/// hashing happens with the GIL released.
+///
+/// Arguments:
+/// - `inputs` (required): An iterable of input buffers.
+/// - `key`: A 32-byte key, for the keyed hashing mode. See the `blake3`
+///   class.
+/// - `derive_key_context`: A context string, for the key derivation mode.
+///   See the `blake3` class.
+/// - `length`: The number of bytes in each hash. Defaults to 32.
+/// - `max_threads`: The maximum number of threads that the implementation
+///   may use. Unlike the `blake3` class, this defaults to `blake3.AUTO`.
+/// - `concatenate`: If True, return all the hashes concatenated into a
+///   single `bytes` object of `length * len(inputs)` bytes, instead of a
+///   list. Defaults to False.
+#[pyfunction]
+#[pyo3(signature=(
+    inputs,
+    /,
+    *,
+    key = None,
+    derive_key_context = None,
+    length = 32,
+    max_threads = Blake3Class::AUTO,
+    concatenate = false
+))]
+fn hash_many<'p>(
+    py: Python<'p>,
+    inputs: &Bound<'p, PyAny>,
+    key: Option<&Bound<PyAny>>,
+    derive_key_context: Option<&str>,
+    length: usize,
+    max_threads: isize,
+    concatenate: bool,
+) -> PyResult<Bound<'p, PyAny>> {
+    let template = new_rust_hasher(key, derive_key_context)?;
+    let threading_mode = ThreadingMode::new(max_threads)?;
+
+    // Acquire all the buffers before releasing the GIL.
+    let mut input_bufs = Vec::new();
+    for input in inputs.try_iter()? {
+        input_bufs.push(BytesPyBuffer::get(&input?)?);
+    }
+    // XXX: Get &[u8] slices of all the inputs. The safety situation here is
+    // complicated. See all the comments in bytes_from_pybuffer.
+    let mut input_slices: Vec<&[u8]> = Vec::with_capacity(input_bufs.len());
+    for input_buf in &input_bufs {
+        input_slices.push(unsafe { input_buf.as_bytes()? });
+    }
+    let total_input_len: usize = input_slices.iter().map(|slice| slice.len()).sum();
+    let Some(total_output_len) = length
+        .checked_mul(input_slices.len())
+        .filter(|&len| len <= isize::MAX as usize)
+    else {
+        return Err(PyOverflowError::new_err(
+            "total output length overflows isize",
+        ));
+    };
+
+    let hash_one = |(output, input): (&mut [u8], &&[u8])| {
+        let mut hasher = template.clone();
+        // In the multithreaded modes, this also splits up large inputs.
+        threading_mode.update(&mut hasher, input);
+        hasher.finalize_xof().fill(output);
+    };
+    let hash_all = |all_outputs: &mut [u8]| {
+        // chunks_mut panics on 0, and there's nothing to do in that case.
+        if length == 0 {
+            return;
+        }
+        match &threading_mode {
+            ThreadingMode::Single => all_outputs
+                .chunks_mut(length)
+                .zip(&input_slices)
+                .for_each(hash_one),
+            ThreadingMode::Auto => all_outputs
+                .par_chunks_mut(length)
+                .zip(&input_slices)
+                .for_each(hash_one),
+            ThreadingMode::Pool { pool, .. } => pool.install(|| {
+                all_outputs
+                    .par_chunks_mut(length)
+                    .zip(&input_slices)
+                    .for_each(hash_one)
+            }),
+        }
+    };
+    let concatenated = PyBytes::new_with(py, total_output_len, |all_outputs| {
+        if total_input_len + total_output_len >= GIL_MINSIZE {
+            py.detach(|| hash_all(all_outputs));
+        } else {
+            hash_all(all_outputs);
+        }
+        Ok(())
+    })?;
+
+    if concatenate {
+        return Ok(concatenated.into_any());
+    }
+    let hashes = PyList::empty(py);
+    for i in 0..input_slices.len() {
+        let hash = &concatenated.as_bytes()[i * length..][..length];
+        hashes.append(PyBytes::new(py, hash))?;
+    }
+    Ok(hashes.into_any())
+}
+
+/// Hash the rows of a 2-D buffer, like a NumPy array of shape `(N, k)` and
+/// dtype `uint8`, and return all their hashes. This is equivalent to calling
+/// `blake3(row).digest(length)` for each row, but rows of up to 1024 bytes
+/// are hashed several at a time with SIMD, which is much faster for large
+/// numbers of short inputs.
+///
+/// Arguments:
+/// - `data` (required): A C-contiguous buffer. If it's 2-D, each row is one
+///   input. Otherwise `row_length` is required, and `data` is split into
+///   rows of that many bytes.
+/// - `row_length`: The number of bytes in each row. For 2-D buffers this is
+///   optional, and if it's given it must match the buffer's shape.
+/// - `key`: A 32-byte key, for the keyed hashing mode. See the `blake3`
+///   class.
+/// - `derive_key_context`: A context string, for the key derivation mode.
+///   See the `blake3` class.
+/// - `length`: The number of bytes in each hash. Defaults to 32.
+/// - `as_int`: If True, return a list of ints instead, each one being a hash
+///   read as a big-endian unsigned integer. Defaults to False.
+/// - `max_threads`: The maximum number of threads that the implementation
+///   may use. Like `hash_many`, this defaults to `blake3.AUTO`.
+///
+/// By default the return value is a single `bytes` object of `N * length`
+/// bytes, with the hash of row `i` at offset `i * length`. To get an `(N,
+/// length)` array, use `numpy.frombuffer(hashes, "uint8").reshape(N,
+/// length)`.
+#[pyfunction]
+#[pyo3(signature=(
+    data,
+    /,
+    *,
+    row_length = None,
+    key = None,
+    derive_key_context = None,
+    length = 32,
+    as_int = false,
+    max_threads = Blake3Class::AUTO
+))]
+#[allow(clippy::too_many_arguments)]
+fn hash_rows<'p>(
+    py: Python<'p>,
+    data: &Bound<'p, PyAny>,
+    row_length: Option<usize>,
+    key: Option<&Bound<PyAny>>,
+    derive_key_context: Option<&str>,
+    length: usize,
+    as_int: bool,
+    max_threads: isize,
+) -> PyResult<Bound<'p, PyAny>> {
+    let row_hasher = new_row_hasher(key, derive_key_context)?;
+    let threading_mode = ThreadingMode::new(max_threads)?;
+    let data_buf = BytesPyBuffer::get(data)?;
+    // XXX: Get a &[u8] slice of the input. The safety situation here is
+    // complicated. See all the comments in bytes_from_pybuffer.
+    let input = unsafe { data_buf.as_bytes()? };
+
+    let (num_rows, row_len) = match (data_buf.shape(), row_length) {
+        (&[num_rows, row_len], None) => (num_rows, row_len),
+        (&[num_rows, row_len], Some(row_length)) if row_length == row_len => (num_rows, row_len),
+        (&[_, row_len], Some(row_length)) => {
+            return Err(PyValueError::new_err(format!(
+                "row_length is {row_length}, but the rows are {row_len} bytes"
+            )));
+        }
+        (_, None) => {
+            return Err(PyValueError::new_err(
+                "row_length is required if the buffer isn't 2-D",
+            ));
+        }
+        (_, Some(0)) => return Err(PyValueError::new_err("row_length must be positive")),
+        (_, Some(row_length)) => {
+            let rows = input.chunks_exact(row_length);
+            if !rows.remainder().is_empty() {
+                return Err(PyValueError::new_err(format!(
+                    "buffer length {} isn't a multiple of row_length {row_length}",
+                    input.len()
+                )));
+            }
+            (rows.len(), row_length)
+        }
+    };
+    let Some(total_output_len) = length
+        .checked_mul(num_rows)
+        .filter(|&len| len <= isize::MAX as usize)
+    else {
+        return Err(PyOverflowError::new_err(
+            "total output length overflows isize",
+        ));
+    };
+
+    let hash_batch = |(outputs, inputs): (&mut [u8], &[u8])| {
+        row_hasher.hash_batch(inputs, row_len, outputs, length);
+    };
+    let hash_all = |all_outputs: &mut [u8]| {
+        // Empty rows all have the same hash, and the chunking below doesn't
+        // work with zero-length rows or outputs anyway.
+        if length == 0 {
+            return;
+        }
+        if row_len == 0 {
+            let mut empty_hash = vec![0; length];
+            row_hasher.hash_empty(&mut empty_hash);
+            for output in all_outputs.chunks_exact_mut(length) {
+                output.copy_from_slice(&empty_hash);
+            }
+            return;
+        }
+        let input_batches = input.chunks(rows::BATCH_ROWS * row_len);
+        match &threading_mode {
+            ThreadingMode::Single => all_outputs
+                .chunks_mut(rows::BATCH_ROWS * length)
+                .zip(input_batches)
+                .for_each(hash_batch),
+            ThreadingMode::Auto => all_outputs
+                .par_chunks_mut(rows::BATCH_ROWS * length)
+                .zip(input.par_chunks(rows::BATCH_ROWS * row_len))
+                .for_each(hash_batch),
+            ThreadingMode::Pool { pool, .. } => pool.install(|| {
+                all_outputs
+                    .par_chunks_mut(rows::BATCH_ROWS * length)
+                    .zip(input.par_chunks(rows::BATCH_ROWS * row_len))
+                    .for_each(hash_batch)
+            }),
+        }
+    };
+    let concatenated = PyBytes::new_with(py, total_output_len, |all_outputs| {
+        if input.len() + total_output_len >= GIL_MINSIZE {
+            py.detach(|| hash_all(all_outputs));
+        } else {
+            hash_all(all_outputs);
+        }
+        Ok(())
+    })?;
+
+    if !as_int {
+        return Ok(concatenated.into_any());
+    }
+    let ints = PyList::empty(py);
+    let int_type = py.get_type::<pyo3::types::PyInt>();
+    for i in 0..num_rows {
+        let hash = &concatenated.as_bytes()[i * length..][..length];
+        if length <= 16 {
+            let mut be_bytes = [0; 16];
+            be_bytes[16 - length..].copy_from_slice(hash);
+            ints.append(u128::from_be_bytes(be_bytes))?;
+        } else {
+            ints.append(int_type.call_method1("from_bytes", (hash, "big"))?)?;
+        }
+    }
+    Ok(ints.into_any())
+}
+
+/// The default buffer size for reading file objects. This matches the
+/// buffered fallback in upstream `update_mmap`, which is large enough for the
+/// SIMD kernels to work with. Multithreaded hashing gets a bigger buffer, so
+/// that each read has enough work to split up.
+const READ_BUFFER_LEN: usize = 64 * 1024;
+const READ_BUFFER_LEN_MULTITHREADED: usize = 1024 * 1024;
+
+fn default_read_buffer_len(threading_mode: &ThreadingMode) -> usize {
+    match threading_mode {
+        ThreadingMode::Single => READ_BUFFER_LEN,
+        _ => READ_BUFFER_LEN_MULTITHREADED,
+    }
+}
+
+/// A fixed-size, writable buffer owned by Rust, which we pass to `readinto`.
+/// It's a Python object, so that if the file object holds onto it (or onto a
+/// memoryview of it), the memory stays alive as long as it needs to, and
+/// unlike a `bytearray` it can't be resized. The bytes are atomics only so
+/// that Python can write to them through a shared reference.
+#[pyclass(module = "blake3.blake3", frozen)]
+struct ReadBuffer {
+    bytes: Box<[AtomicU8]>,
+}
+
+impl ReadBuffer {
+    fn new(len: usize) -> Self {
+        Self {
+            bytes: (0..len).map(|_| AtomicU8::new(0)).collect(),
+        }
+    }
+
+    /// Get a &[u8] of the first `len` bytes, or None if that's too many. This
+    /// is unsafe for the same reasons as `BytesPyBuffer::as_bytes`, if the
+    /// file object kept a reference to the buffer and writes to it from
+    /// another thread.
+    unsafe fn as_bytes(&self, len: usize) -> Option<&[u8]> {
+        if len > self.bytes.len() {
+            return None;
+        }
+        Some(unsafe { std::slice::from_raw_parts(self.bytes.as_ptr() as *const u8, len) })
+    }
+}
+
+#[pymethods]
+impl ReadBuffer {
+    fn __len__(&self) -> usize {
+        self.bytes.len()
+    }
+
+    unsafe fn __getbuffer__(
+        slf: Bound<'_, Self>,
+        view: *mut pyo3::ffi::Py_buffer,
+        flags: std::os::raw::c_int,
+    ) -> PyResult<()> {
+        let bytes = &slf.get().bytes;
+        // This fills in a plain, writable, 1-D buffer of unsigned bytes, and
+        // keeps a reference to this object until the buffer is released.
+        let ret = unsafe {
+            pyo3::ffi::PyBuffer_FillInfo(
+                view,
+                slf.as_ptr(),
+                bytes.as_ptr() as *mut std::os::raw::c_void,
+                bytes.len() as pyo3::ffi::Py_ssize_t,
+                0,
+                flags,
+            )
+        };
+        if ret == -1 {
+            return Err(PyErr::fetch(slf.py()));
+        }
+        Ok(())
+    }
+}
+
+/// If a file object is backed by a regular file that's worth memory mapping,
+/// map it from the object's current position. Objects like `BytesIO` and
+/// sockets don't have a usable `fileno`, and for those we return None.
+///
+/// Only plain `io.FileIO` objects, and `io.BufferedReader` or
+/// `io.BufferedRandom` objects wrapping one, return the file's bytes
+/// unchanged. Other objects with a `fileno`, like `gzip.GzipFile`, return
+/// something different from what's in the file, so we return None for
+/// anything else, and the caller reads through `readinto`. We check exact
+/// types, because a subclass could override `read`.
+fn mmap_fileobj(py: Python, fileobj: &Bound<PyAny>) -> PyResult<Option<memmap2::Mmap>> {
+    let io_module = py.import("io")?;
+    let is_type = |obj: &Bound<PyAny>, name: &str| -> PyResult<bool> {
+        Ok(obj.get_type().is(&io_module.getattr(name)?))
+    };
+    let is_raw_file = if is_type(fileobj, "FileIO")? {
+        true
+    } else if is_type(fileobj, "BufferedReader")? || is_type(fileobj, "BufferedRandom")? {
+        match fileobj.getattr("raw") {
+            Ok(raw) => is_type(&raw, "FileIO")?,
+            Err(_) => false,
+        }
+    } else {
+        false
+    };
+    if !is_raw_file {
+        return Ok(None);
+    }
+    let call_bool = |name: &str| -> bool {
+        fileobj
+            .call_method0(name)
+            .and_then(|result| result.is_truthy())
+            .unwrap_or(false)
+    };
+    let Ok(fd) = fileobj
+        .call_method0("fileno")
+        .and_then(|fd| fd.extract::<i32>())
+    else {
+        return Ok(None);
+    };
+    if !call_bool("readable") || !call_bool("seekable") {
+        return Ok(None);
+    }
+    // The object's own position accounts for any read-ahead buffering, which
+    // the position of the file descriptor doesn't.
+    let position: u64 = fileobj.call_method0("tell")?.extract()?;
+    let file = files::borrow_fd(py, fd)?;
+    Ok(files::maybe_mmap_file(&file, position)?)
+}
+
+/// Read a binary file object until EOF and pass its contents to `update`. If
+/// the object is backed by a large regular file, this memory maps the file
+/// from the object's current position, and then seeks the object to the end.
+/// Otherwise it reads through `readinto`, which respects the object's own
+/// buffering. Either way, `update` is called with the GIL released, except
+/// for reads of `n` bytes where `detach(n)` returns false.
+fn update_from_fileobj(
+    py: Python,
+    fileobj: &Bound<PyAny>,
+    buffer_len: usize,
+    detach: impl Fn(usize) -> bool,
+    mut update: impl FnMut(&[u8]) + Send,
+) -> PyResult<()> {
+    // Check for readinto first, even if we end up memory mapping, so that we
+    // reject text files consistently.
+    let Ok(readinto) = fileobj.getattr("readinto") else {
+        return Err(PyTypeError::new_err(
+            "expected a path or a file object opened in binary mode",
+        ));
+    };
+    if buffer_len == 0 {
+        return Err(PyValueError::new_err("buffer_size must be positive"));
+    }
+
+    if let Some(mmap) = mmap_fileobj(py, fileobj)? {
+        py.detach(|| update(&mmap));
+        fileobj.call_method1("seek", (0, 2))?;
+        return Ok(());
+    }
+
+    let buffer = Bound::new(py, ReadBuffer::new(buffer_len))?;
+    loop {
+        let result = readinto.call1((&buffer,))?;
+        if result.is_none() {
+            return Err(PyBlockingIOError::new_err(
+                "file object is non-blocking and has no data available",
+            ));
+        }
+        let n: usize = result.extract()?;
+        if n == 0 {
+            return Ok(());
+        }
+        // XXX: Get a &[u8] slice of the buffer. See ReadBuffer::as_bytes.
+        let Some(data) = (unsafe { buffer.get().as_bytes(n) }) else {
+            return Err(PyValueError::new_err(format!(
+                "readinto returned {n}, but the buffer is {buffer_len} bytes"
+            )));
+        };
+        if detach(n) {
+            py.detach(|| update(data));
+        } else {
+            update(data);
+        }
+    }
+}
+
+/// Hash a file and return the hash as bytes. This is equivalent to reading
+/// the whole file into a `blake3` object, but it takes care of choosing the
+/// fastest way to read it: memory mapping for large regular files, and
+/// buffered reads for small files and for things like pipes and character
+/// devices.
+///
+/// Arguments:
+/// - `path_or_fileobj` (required): A path (`str` or `os.PathLike`) or a file
+///   object opened in binary mode, like `sys.stdin.buffer`. File objects are
+///   read from their current position until EOF.
+/// - `key`: A 32-byte key, for the keyed hashing mode. See the `blake3`
+///   class.
+/// - `derive_key_context`: A context string, for the key derivation mode.
+///   See the `blake3` class.
+/// - `length`: The number of bytes in the final hash. Defaults to 32.
+/// - `max_threads`: The maximum number of threads that the implementation
+///   may use. Like `hash_many`, this defaults to `blake3.AUTO`.
+/// - `offset`: For paths only, the byte offset to start hashing from.
+///   Defaults to 0.
+/// - `size`: For paths only, the number of bytes to hash. (`length` is the
+///   length of the output, like everywhere else.) By default, hash to the
+///   end of the file. See `blake3.update_mmap` for the requirements.
+/// - `as_hash`: If True, return a `blake3.Hash` instead, like
+///   `blake3.finalize`. This requires the default `length` of 32. Defaults
+///   to False.
+#[pyfunction]
+#[pyo3(signature=(
+    path_or_fileobj,
+    /,
+    *,
+    key = None,
+    derive_key_context = None,
+    length = 32,
+    max_threads = Blake3Class::AUTO,
+    offset = None,
+    size = None,
+    as_hash = false
+))]
+#[allow(clippy::too_many_arguments)]
+fn hash_file<'p>(
+    py: Python<'p>,
+    path_or_fileobj: &Bound<PyAny>,
+    key: Option<&Bound<PyAny>>,
+    derive_key_context: Option<&str>,
+    length: usize,
+    max_threads: isize,
+    offset: Option<u64>,
+    size: Option<u64>,
+    as_hash: bool,
+) -> PyResult<Bound<'p, PyAny>> {
+    if length > isize::MAX as usize {
+        return Err(PyOverflowError::new_err("length overflows isize"));
+    }
+    if as_hash && length != upstream_blake3::OUT_LEN {
+        return Err(PyValueError::new_err(format!(
+            "as_hash requires a length of {}, not {length}",
+            upstream_blake3::OUT_LEN,
+        )));
+    }
+    let mut hasher = new_rust_hasher(key, derive_key_context)?;
+    let threading_mode = ThreadingMode::new(max_threads)?;
+
+    if let Ok(path) = path_or_fileobj.extract::<PathBuf>() {
+        py.detach(|| threading_mode.update_mmap_range(&mut hasher, &path, offset, size))?;
+    } else if offset.is_some() || size.is_some() {
+        return Err(PyValueError::new_err(
+            "offset and size are only supported for paths",
+        ));
+    } else {
+        let buffer_len = default_read_buffer_len(&threading_mode);
+        update_from_fileobj(
+            py,
+            path_or_fileobj,
+            buffer_len,
+            |n| n >= GIL_MINSIZE,
+            |data| threading_mode.update(&mut hasher, data),
+        )?;
+    }
+
+    if as_hash {
+        let hash = HashClass {
+            rust_hash: hasher.finalize(),
+        };
+        return Ok(Bound::new(py, hash)?.into_any());
+    }
+    let mut reader = hasher.finalize_xof();
+    let output = PyBytes::new_with(py, length, |slice| {
+        fill_output(py, &threading_mode, &mut reader, slice);
+        Ok(())
+    })?;
+    Ok(output.into_any())
+}
+
+/// How many finished results a `HashFilesIterator` can hold before the
+/// background hashing waits for the caller to catch up.
+const HASH_FILES_STREAM_CAPACITY: usize = 1024;
+
+/// Hash many files in parallel, and return a dict mapping each path to its
+/// hash. This is equivalent to calling `hash_file` on each path, but all the
+/// work happens with the GIL released, and multiple files are hashed at
+/// once.
+///
+/// Arguments:
+/// - `paths` (required): A path (`str` or `os.PathLike`), or an iterable of
+///   them. Directories are walked recursively, in sorted order. Inside
+///   directories, special files like pipes and sockets are skipped.
+/// - `key`: A 32-byte key, for the keyed hashing mode. See the `blake3`
+///   class.
+/// - `derive_key_context`: A context string, for the key derivation mode.
+///   See the `blake3` class.
+/// - `length`: The number of bytes in each hash. Defaults to 32.
+/// - `max_threads`: The maximum number of threads that the implementation
+///   may use. Like `hash_many`, this defaults to `blake3.AUTO`.
+/// - `follow_symlinks`: If True, follow symlinks found while walking
+///   directories. If False, skip them. Paths given directly are always
+///   followed. Defaults to False, like `os.walk`.
+/// - `include`: An iterable of glob patterns. If given, only files matching
+///   at least one pattern are hashed when walking directories.
+/// - `exclude`: An iterable of glob patterns. Files and directories matching
+///   any pattern are skipped when walking directories.
+/// - `on_error`: What to do when a file can't be read. "raise" (the default)
+///   raises an `OSError`, "skip" leaves the file out, and "return" puts the
+///   `OSError` in place of the hash. Running out of memory for a hash raises
+///   `MemoryError` either way.
+/// - `stream`: If True, return an iterator of `(path, hash)` pairs instead
+///   of a dict, which yields each result as soon as it's ready. Defaults to
+///   False.
+///
+/// In globs, `*` matches anything but `/`, `?` matches any one character,
+/// `[...]` matches a character class, and a `**` component matches any number
+/// of directories. Patterns without a `/` match the file name, and patterns
+/// with a `/` match the whole path relative to the directory given in
+/// `paths`. For example, `exclude=[".git", "build/**/*.o"]`.
+///
+/// Keys are `pathlib.Path` objects. The dict is in walk order, and an
+/// iterator yields results in the order they finish.
+#[pyfunction]
+#[pyo3(signature=(
+    paths,
+    /,
+    *,
+    key = None,
+    derive_key_context = None,
+    length = 32,
+    max_threads = Blake3Class::AUTO,
+    follow_symlinks = false,
+    include = None,
+    exclude = None,
+    on_error = "raise",
+    stream = false
+))]
+#[allow(clippy::too_many_arguments)]
+fn hash_files<'p>(
+    py: Python<'p>,
+    paths: &Bound<PyAny>,
+    key: Option<&Bound<PyAny>>,
+    derive_key_context: Option<&str>,
+    length: usize,
+    max_threads: isize,
+    follow_symlinks: bool,
+    include: Option<Vec<String>>,
+    exclude: Option<Vec<String>>,
+    on_error: &str,
+    stream: bool,
+) -> PyResult<Bound<'p, PyAny>> {
+    let on_error = OnError::from_name(on_error)?;
+    let globs = |patterns: Option<Vec<String>>| -> PyResult<Vec<files::Glob>> {
+        patterns
+            .unwrap_or_default()
+            .iter()
+            .map(|p| files::Glob::new(p))
+            .collect()
+    };
+    let options = files::WalkOptions {
+        follow_symlinks,
+        include: globs(include)?,
+        exclude: globs(exclude)?,
+    };
+    let roots = match paths.extract::<PathBuf>() {
+        Ok(path) => vec![path],
+        Err(_) => {
+            let mut roots = Vec::new();
+            for path in paths.try_iter()? {
+                roots.push(path?.extract::<PathBuf>()?);
+            }
+            roots
+        }
+    };
+    let files_hasher = FilesHasher::new(
+        new_rust_hasher(key, derive_key_context)?,
+        ThreadingMode::new(max_threads)?,
+        length,
+    )?;
+    let walk = files::Walk::new(roots, options);
+
+    if stream {
+        let (sender, receiver) = mpsc::sync_channel(HASH_FILES_STREAM_CAPACITY);
+        // If the iterator is dropped, sending fails, and hashing stops early.
+        std::thread::spawn(move || files_hasher.hash_to_channel(walk, sender));
+        let iterator = HashFilesIterator {
+            receiver: Mutex::new(receiver),
+            on_error,
+        };
+        return Ok(Bound::new(py, iterator)?.into_any());
+    }
+
+    let results = py.detach(|| files_hasher.hash_in_order(walk));
+    let dict = PyDict::new(py);
+    for file_result in results {
+        if let Some((path, value)) = file_result_to_py(py, file_result, on_error)? {
+            dict.set_item(path, value)?;
+        }
+    }
+    Ok(dict.into_any())
+}
+
+/// Hash files and return checksum lines in the same format as `b3sum`,
+/// `"<hash>  <path>\n"`, so that `file.writelines(checksum_lines(paths))`
+/// writes a checksum file that `b3sum --check` and `blake3.check` can read.
+/// Paths containing backslashes or newlines are escaped the same way as
+/// `b3sum` escapes them.
+///
+/// Arguments:
+/// - `paths` (required): An iterable of paths (`str` or `os.PathLike`).
+///   Unlike `hash_files`, directories aren't walked, and any error raises
+///   an `OSError`.
+/// - `length`: The number of bytes in each hash, like `b3sum --length`.
+///   Defaults to 32.
+/// - `max_threads`: The maximum number of threads that the implementation
+///   may use. Like `hash_many`, this defaults to `blake3.AUTO`.
+#[pyfunction]
+#[pyo3(signature=(paths, /, *, length = 32, max_threads = Blake3Class::AUTO))]
+fn checksum_lines(
+    py: Python,
+    paths: &Bound<PyAny>,
+    length: usize,
+    max_threads: isize,
+) -> PyResult<Vec<String>> {
+    let files_hasher = FilesHasher::new(
+        upstream_blake3::Hasher::new(),
+        ThreadingMode::new(max_threads)?,
+        length,
+    )?;
+    let mut items = Vec::new();
+    for path in paths.try_iter()? {
+        items.push(Ok(path?.extract::<PathBuf>()?));
+    }
+    let results = py.detach(|| files_hasher.hash_in_order(items));
+    let mut lines = Vec::with_capacity(results.len());
+    for (path, result) in results {
+        match result {
+            Ok(hash) => lines.push(checksums::format_line(&hash, &path)),
+            Err(e) => return Err(files::os_error(&path, e)),
+        }
+    }
+    Ok(lines)
+}
+
+/// Verify the files listed in a checksum file, like `b3sum --check`, and
+/// return a list of `CheckResult` objects, one per line. The files are
+/// hashed in parallel. Relative paths in the checksum file are relative to
+/// the current directory, which is also how `b3sum` treats them.
+///
+/// Arguments:
+/// - `checkfile` (required): The path to a checksum file, or a file object
+///   to read one from. Every line must be in the format that `b3sum` and
+///   `checksum_lines` produce, or this raises ValueError. Hashes of any
+///   length are accepted.
+/// - `max_threads`: The maximum number of threads that the implementation
+///   may use. Like `hash_many`, this defaults to `blake3.AUTO`.
+#[pyfunction]
+#[pyo3(signature=(checkfile, /, *, max_threads = Blake3Class::AUTO))]
+fn check(py: Python, checkfile: &Bound<PyAny>, max_threads: isize) -> PyResult<Vec<CheckResult>> {
+    let threading_mode = ThreadingMode::new(max_threads)?;
+    let contents: String = if let Ok(path) = checkfile.extract::<PathBuf>() {
+        let bytes = std::fs::read(&path).map_err(|e| files::os_error(&path, e))?;
+        String::from_utf8(bytes)
+            .map_err(|_| PyValueError::new_err("checksum file is not valid UTF-8"))?
+    } else {
+        let data = checkfile.call_method0("read")?;
+        match data.cast::<PyBytes>() {
+            Ok(bytes) => std::str::from_utf8(bytes.as_bytes())
+                .map_err(|_| PyValueError::new_err("checksum file is not valid UTF-8"))?
+                .to_owned(),
+            Err(_) => data.extract()?,
+        }
+    };
+
+    let mut expected_hashes = Vec::new();
+    let mut paths = Vec::new();
+    for (i, line) in contents.lines().enumerate() {
+        let (hash, path) = checksums::parse_line(line).map_err(|msg| {
+            PyValueError::new_err(format!("invalid checksum line {}: {msg}", i + 1))
+        })?;
+        expected_hashes.push(hash);
+        paths.push(path);
+    }
+
+    // Extended outputs are all prefixes of each other, so hashing each file
+    // once with the longest length covers every line.
+    let files_hasher = FilesHasher::new(
+        upstream_blake3::Hasher::new(),
+        threading_mode,
+        expected_hashes.iter().map(Vec::len).max().unwrap_or(0),
+    )?;
+    let items = paths.iter().map(|path| Ok(PathBuf::from(path)));
+    let results = py.detach(|| files_hasher.hash_in_order(items));
+
+    let mut check_results = Vec::with_capacity(results.len());
+    for ((path, result), (path_string, expected)) in results
+        .into_iter()
+        .zip(paths.into_iter().zip(expected_hashes))
+    {
+        let (status, error) = match result {
+            Ok(hash) if hash[..expected.len()] == expected[..] => ("OK", None),
+            Ok(_) => ("FAILED", None),
+            Err(e) if e.kind() == io::ErrorKind::OutOfMemory => return Err(e.into()),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => ("MISSING", Some(e)),
+            Err(e) => ("ERROR", Some(e)),
+        };
+        check_results.push(CheckResult {
+            path: path_string,
+            status,
+            error: error.map(|e| files::os_error(&path, e).into_value(py).into_any()),
+        });
+    }
+    Ok(check_results)
+}
+
+/// Like `PyString::new`, but raise `MemoryError` if Python can't allocate the
+/// string, instead of panicking. Encoded outputs can be huge.
+fn new_pystring<'p>(py: Python<'p>, s: &str) -> PyResult<Bound<'p, PyString>> {
+    // Safety: A `&str` is valid UTF-8, and its length is at most isize::MAX.
+    unsafe {
+        let ptr = pyo3::ffi::PyUnicode_FromStringAndSize(s.as_ptr().cast(), s.len() as isize);
+        Ok(Bound::from_owned_ptr_or_err(py, ptr)?.cast_into_unchecked())
+    }
+}
+
+/// Compare two buffers (like `bytes`) in constant time, and return True if
+/// they're equal. This is similar to `hmac.compare_digest` from the
+/// standard library. Comparing buffers of different lengths returns False
+/// immediately, so the lengths themselves are not secret.
+///
+/// Arguments:
+/// - `a` (required): The first buffer.
+/// - `b` (required): The second buffer.
+#[pyfunction]
+#[pyo3(signature=(a, b, /))]
+fn compare_digest(a: &Bound<PyAny>, b: &Bound<PyAny>) -> PyResult<bool> {
+    // XXX: Get &[u8] slices of both buffers. The safety situation here is
+    // complicated. See all the comments in bytes_from_pybuffer.
+    let a_buf = BytesPyBuffer::get(a)?;
+    let a_slice: &[u8] = unsafe { a_buf.as_bytes()? };
+    let b_buf = BytesPyBuffer::get(b)?;
+    let b_slice: &[u8] = unsafe { b_buf.as_bytes()? };
+    Ok(constant_time_eq::constant_time_eq(a_slice, b_slice))
+}
+
+/// Encode a digest (or any other bytes) as a string, using any of the
+/// encodings supported by `blake3.encoded_digest`.
+///
+/// Arguments:
+/// - `data` (required): The bytes to encode.
+/// - `encoding` (required): The name of the encoding.
+/// - `uppercase`: Use uppercase letters. See `encoded_digest`.
+/// - `sep`: A separator to insert between bytes. See `encoded_digest`.
+#[pyfunction]
+#[pyo3(signature=(data, encoding, /, *, uppercase=false, sep=None))]
+fn encode_digest<'p>(
+    py: Python<'p>,
+    data: &Bound<PyAny>,
+    encoding: &str,
+    uppercase: bool,
+    sep: Option<&str>,
+) -> PyResult<Bound<'p, PyString>> {
+    let encoding = Encoding::from_name(encoding)?;
+    // XXX: Get a &[u8] slice of the data bytes. The safety situation here is
+    // complicated. See all the comments in bytes_from_pybuffer.
+    let data_buf = BytesPyBuffer::get(data)?;
+    let data_slice: &[u8] = unsafe { data_buf.as_bytes()? };
+    let encoded = if data_slice.len() >= GIL_MINSIZE {
+        py.detach(|| encoding.encode(data_slice, uppercase, sep))?
+    } else {
+        encoding.encode(data_slice, uppercase, sep)?
+    };
+    new_pystring(py, &encoded)
+}
+
+/// Decode a string produced by `encoded_digest` or `encode_digest` back into
+/// bytes. Raises `ValueError` if the string isn't valid for the encoding.
+/// Decoding "hex", "base32" and "base32crockford" is case-insensitive.
+///
+/// Arguments:
+/// - `text` (required): The string to decode.
+/// - `encoding` (required): The name of the encoding.
+/// - `sep`: A separator to skip, for "hex" only. Defaults to None.
+#[pyfunction]
+#[pyo3(signature=(text, encoding, /, *, sep=None))]
+fn decode_digest<'p>(
+    py: Python<'p>,
+    text: &str,
+    encoding: &str,
+    sep: Option<&str>,
+) -> PyResult<Bound<'p, PyBytes>> {
+    let encoding = Encoding::from_name(encoding)?;
+    let decoded = encoding.decode(text, sep)?;
+    Ok(PyBytes::new(py, &decoded))
+}
+
+/// Parse a BLAKE3 multihash, like the output of `blake3.multihash`, and
+/// return the digest bytes. Raises `ValueError` if the input isn't exactly
+/// one multihash, or if its hash function isn't BLAKE3.
+///
+/// Arguments:
+/// - `data` (required): The multihash bytes.
+#[pyfunction]
+#[pyo3(signature=(data, /))]
+fn parse_multihash<'p>(py: Python<'p>, data: &Bound<PyAny>) -> PyResult<Bound<'p, PyBytes>> {
+    // XXX: Get a &[u8] slice of the data bytes. The safety situation here is
+    // complicated. See all the comments in bytes_from_pybuffer.
+    let data_buf = BytesPyBuffer::get(data)?;
+    let data_slice: &[u8] = unsafe { data_buf.as_bytes()? };
+    Ok(PyBytes::new(py, multiformats::parse_multihash(data_slice)?))
+}
+
+/// Parse a CIDv1 string, like the output of `blake3.cid`, and return the
+/// digest bytes. Raises `ValueError` if the CID is malformed, if its
+/// multibase encoding isn't supported, or if its hash function isn't
+/// BLAKE3. Any codec is allowed.
+///
+/// Arguments:
+/// - `cid` (required): The CID string.
+#[pyfunction]
+#[pyo3(signature=(cid, /))]
+fn parse_cid<'p>(py: Python<'p>, cid: &str) -> PyResult<Bound<'p, PyBytes>> {
+    Ok(PyBytes::new(py, &multiformats::parse_cid(cid)?))
 }
 
 /// Python bindings for the official Rust implementation of BLAKE3
-/// (https://github.com/BLAKE3-team/BLAKE3). This module provides a single
-/// class, also called `blake3.` The interface is similar to `hashlib` from
-/// the standard library, which provides `blake2b`, `md5`, etc.
+/// (https://github.com/BLAKE3-team/BLAKE3). The main interface is a class,
+/// also called `blake3.` The interface is similar to `hashlib` from the
+/// standard library, which provides `blake2b`, `md5`, etc.
 #[pymodule(gil_used = false)]
-fn blake3(_: Python, m: &Bound<PyModule>) -> PyResult<()> {
+fn blake3(py: Python, m: &Bound<PyModule>) -> PyResult<()> {
     m.add_class::<Blake3Class>()?;
+    m.add_class::<HashClass>()?;
+    m.add_class::<OutputReaderClass>()?;
+    m.add_class::<HashFilesIterator>()?;
+    m.add_class::<CheckResult>()?;
+    m.add_function(wrap_pyfunction!(hash, m)?)?;
+    m.add_function(wrap_pyfunction!(keyed_hash, m)?)?;
+    m.add_function(wrap_pyfunction!(derive_key, m)?)?;
+    m.add_function(wrap_pyfunction!(hash_many, m)?)?;
+    m.add_function(wrap_pyfunction!(hash_rows, m)?)?;
+    m.add_function(wrap_pyfunction!(hash_file, m)?)?;
+    m.add_function(wrap_pyfunction!(hash_files, m)?)?;
+    m.add_function(wrap_pyfunction!(checksum_lines, m)?)?;
+    m.add_function(wrap_pyfunction!(check, m)?)?;
+    m.add_function(wrap_pyfunction!(set_snapshot_default, m)?)?;
+    m.add_function(wrap_pyfunction!(get_snapshot_default, m)?)?;
+    m.add_function(wrap_pyfunction!(compare_digest, m)?)?;
+    m.add_function(wrap_pyfunction!(encode_digest, m)?)?;
+    m.add_function(wrap_pyfunction!(decode_digest, m)?)?;
+    m.add_function(wrap_pyfunction!(parse_multihash, m)?)?;
+    m.add_function(wrap_pyfunction!(parse_cid, m)?)?;
+    m.add("VerificationError", py.get_type::<VerificationError>())?;
+    // OutputReader implements the io.RawIOBase interface, but PyO3 classes
+    // can't inherit from Python classes, so register it as a virtual subclass.
+    py.import("io")?
+        .getattr("RawIOBase")?
+        .call_method1("register", (m.getattr("OutputReader")?,))?;
+    m.add("AUTO", Blake3Class::AUTO)?;
     m.add("__version__", env!("CARGO_PKG_VERSION"))?;
     Ok(())
 }
@@ -0,0 +1,219 @@
+//! Multihash and CID framing for BLAKE3 digests, as specified at
+//! https://github.com/multiformats. Like `encoding`, this module works on
+//! plain Rust slices and strings.
+
+use crate::encoding::Encoding;
+use pyo3::exceptions::PyValueError;
+use pyo3::prelude::*;
+use pyo3::types::PyString;
+
+/// The registered multihash code for BLAKE3. BLAKE3 is an XOF, so unlike most
+/// other hash functions, this code allows any digest length.
+pub(crate) const BLAKE3_MULTIHASH_CODE: u64 = 0x1e;
+
+/// The CID version that we produce. CIDv0 is implicitly SHA2-256, so BLAKE3
+/// digests always need CIDv1.
+const CID_VERSION: u64 = 1;
+
+/// The multiformats spec limits varints to 9 bytes, i.e. 63 bits.
+const MAX_VARINT_LEN: usize = 9;
+
+/// The default codec for CIDs, for content that's just bytes.
+pub(crate) const RAW_CODEC: u64 = 0x55;
+
+/// Some common codecs from the multicodec table, so that callers can use names
+/// instead of numbers.
+const CODECS: &[(&str, u64)] = &[
+    ("cbor", 0x51),
+    ("raw", RAW_CODEC),
+    ("dag-pb", 0x70),
+    ("dag-cbor", 0x71),
+    ("dag-json", 0x0129),
+    ("json", 0x0200),
+];
+
+/// Get a multicodec code from either an integer or one of the names above.
+pub(crate) fn codec_from_py(codec: &Bound<PyAny>) -> PyResult<u64> {
+    if let Ok(name) = codec.cast::<PyString>() {
+        return codec_from_name(name.to_str()?);
+    }
+    let code: u64 = codec.extract()?;
+    if code >= 1 << 63 {
+        return Err(PyValueError::new_err("codec doesn't fit in a varint"));
+    }
+    Ok(code)
+}
+
+fn codec_from_name(name: &str) -> PyResult<u64> {
+    match CODECS.iter().find(|(codec_name, _)| *codec_name == name) {
+        Some((_, code)) => Ok(*code),
+        None => {
+            let names: Vec<&str> = CODECS.iter().map(|(name, _)| *name).collect();
+            let msg = format!(
+                "unknown codec {name:?}, expected an integer or one of: {}",
+                names.join(", "),
+            );
+            Err(PyValueError::new_err(msg))
+        }
+    }
+}
+
+/// The multibase encodings we support. Each one is a single prefix character
+/// followed by the encoded bytes.
+#[derive(Clone, Copy)]
+struct Multibase {
+    name: &'static str,
+    prefix: char,
+    encoding: Encoding,
+    uppercase: bool,
+}
+
+const MULTIBASES: &[Multibase] = &[
+    Multibase {
+        name: "base16",
+        prefix: 'f',
+        encoding: Encoding::Hex,
+        uppercase: false,
+    },
+    Multibase {
+        name: "base16upper",
+        prefix: 'F',
+        encoding: Encoding::Hex,
+        uppercase: true,
+    },
+    Multibase {
+        name: "base32",
+        prefix: 'b',
+        encoding: Encoding::Base32,
+        uppercase: false,
+    },
+    Multibase {
+        name: "base32upper",
+        prefix: 'B',
+        encoding: Encoding::Base32,
+        uppercase: true,
+    },
+    Multibase {
+        name: "base58btc",
+        prefix: 'z',
+        encoding: Encoding::Base58,
+        uppercase: false,
+    },
+    Multibase {
+        name: "base64url",
+        prefix: 'u',
+        encoding: Encoding::Base64Url,
+        uppercase: false,
+    },
+];
+
+fn push_varint(out: &mut Vec<u8>, mut value: u64) {
+    while value >= 0x80 {
+        out.push((value as u8) | 0x80);
+        value >>= 7;
+    }
+    out.push(value as u8);
+}
+
+/// Parse a varint from the front of `input`, and return it along with the
+/// rest of the input. Non-minimal encodings are rejected, as the spec
+/// requires.
+fn take_varint(input: &[u8]) -> Result<(u64, &[u8]), &'static str> {
+    let mut value: u64 = 0;
+    for (i, &byte) in input.iter().enumerate().take(MAX_VARINT_LEN) {
+        value |= ((byte & 0x7f) as u64) << (7 * i);
+        if byte & 0x80 == 0 {
+            if byte == 0 && i > 0 {
+                return Err("varint is not minimally encoded");
+            }
+            return Ok((value, &input[i + 1..]));
+        }
+    }
+    if input.len() < MAX_VARINT_LEN {
+        Err("input ends in the middle of a varint")
+    } else {
+        Err("varint is too long")
+    }
+}
+
+/// Frame a BLAKE3 digest as a multihash: the BLAKE3 code, the digest length,
+/// and then the digest itself.
+pub(crate) fn multihash(digest: &[u8]) -> PyResult<Vec<u8>> {
+    if digest.is_empty() {
+        return Err(PyValueError::new_err("multihash digests can't be empty"));
+    }
+    let mut out = Vec::with_capacity(digest.len() + 2 * MAX_VARINT_LEN);
+    push_varint(&mut out, BLAKE3_MULTIHASH_CODE);
+    push_varint(&mut out, digest.len() as u64);
+    out.extend_from_slice(digest);
+    Ok(out)
+}
+
+/// Check that `input` is exactly one BLAKE3 multihash, and return the digest.
+pub(crate) fn parse_multihash(input: &[u8]) -> PyResult<&[u8]> {
+    let invalid = |msg: &str| PyValueError::new_err(format!("invalid multihash: {msg}"));
+    let (code, rest) = take_varint(input).map_err(invalid)?;
+    if code != BLAKE3_MULTIHASH_CODE {
+        return Err(invalid(&format!(
+            "expected the BLAKE3 code {BLAKE3_MULTIHASH_CODE:#x}, found {code:#x}"
+        )));
+    }
+    let (length, digest) = take_varint(rest).map_err(invalid)?;
+    if length == 0 {
+        return Err(invalid("the digest is empty"));
+    }
+    if length != digest.len() as u64 {
+        return Err(invalid(&format!(
+            "the digest length is {length}, but {} bytes follow",
+            digest.len(),
+        )));
+    }
+    Ok(digest)
+}
+
+/// Build a CIDv1 string from a BLAKE3 digest.
+pub(crate) fn cid(digest: &[u8], codec: u64, base: &str) -> PyResult<String> {
+    let Some(multibase) = MULTIBASES.iter().find(|m| m.name == base) else {
+        let names: Vec<&str> = MULTIBASES.iter().map(|m| m.name).collect();
+        let msg = format!(
+            "unknown base {base:?}, expected one of: {}",
+            names.join(", ")
+        );
+        return Err(PyValueError::new_err(msg));
+    };
+    let mut binary = Vec::new();
+    push_varint(&mut binary, CID_VERSION);
+    push_varint(&mut binary, codec);
+    binary.extend_from_slice(&multihash(digest)?);
+    let mut out = String::new();
+    out.push(multibase.prefix);
+    out.push_str(
+        &multibase
+            .encoding
+            .encode(&binary, multibase.uppercase, None)?,
+    );
+    Ok(out)
+}
+
+/// Check that `text` is a CIDv1 string containing a BLAKE3 multihash, and
+/// return the digest. Any codec is allowed.
+pub(crate) fn parse_cid(text: &str) -> PyResult<Vec<u8>> {
+    let invalid = |msg: &str| PyValueError::new_err(format!("invalid CID: {msg}"));
+    let mut chars = text.chars();
+    let Some(prefix) = chars.next() else {
+        return Err(invalid("the string is empty"));
+    };
+    let Some(multibase) = MULTIBASES.iter().find(|m| m.prefix == prefix) else {
+        if text.starts_with("Qm") {
+            return Err(invalid("CIDv0 is always SHA2-256, not BLAKE3"));
+        }
+        return Err(invalid(&format!("unsupported multibase prefix {prefix:?}")));
+    };
+    let binary = multibase.encoding.decode(chars.as_str(), None)?;
+    let (version, rest) = take_varint(&binary).map_err(invalid)?;
+    if version != CID_VERSION {
+        return Err(invalid(&format!("expected CIDv1, found version {version}")));
+    }
+    let (_codec, multihash) = take_varint(rest).map_err(invalid)?;
+    Ok(parse_multihash(multihash)?.to_vec())
+}
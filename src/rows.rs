@@ -0,0 +1,211 @@
+//! Hashing many short inputs of the same length with the upstream SIMD
+//! kernels, for `hash_rows`.
+//!
+//! The incremental `Hasher` only uses the multi-input `hash_many` kernels for
+//! chunks within one long input. Here we use them across inputs instead: when
+//! every input fits in a single 1 KiB chunk, each input is its own root chunk,
+//! and a batch of inputs can be compressed in parallel SIMD lanes just like
+//! the chunks of a long input. The `platform` module that provides these
+//! kernels is hidden from the upstream docs, which is why Cargo.toml pins the
+//! upstream minor version.
+
+use upstream_blake3::platform::Platform;
+use upstream_blake3::{BLOCK_LEN, CHUNK_LEN, IncrementCounter, OUT_LEN};
+
+// These constants come from the BLAKE3 spec. Upstream doesn't export them.
+const IV: [u32; 8] = [
+    0x6A09E667, 0xBB67AE85, 0x3C6EF372, 0xA54FF53A, 0x510E527F, 0x9B05688C, 0x1F83D9AB, 0x5BE0CD19,
+];
+const CHUNK_START: u8 = 1 << 0;
+const CHUNK_END: u8 = 1 << 1;
+const ROOT: u8 = 1 << 3;
+const KEYED_HASH: u8 = 1 << 4;
+const DERIVE_KEY_MATERIAL: u8 = 1 << 6;
+
+/// How many rows each call to `hash_many` gets. This is a multiple of every
+/// SIMD degree, and it keeps the temporary buffers below small.
+pub(crate) const BATCH_ROWS: usize = 64;
+
+/// Hashes rows in one of the three BLAKE3 modes. Rows longer than one chunk
+/// fall back to the regular `Hasher`.
+#[derive(Clone)]
+pub(crate) struct RowHasher {
+    platform: Platform,
+    key_words: [u32; 8],
+    flags: u8,
+    fallback: upstream_blake3::Hasher,
+}
+
+impl RowHasher {
+    pub(crate) fn new() -> Self {
+        Self {
+            platform: Platform::detect(),
+            key_words: IV,
+            flags: 0,
+            fallback: upstream_blake3::Hasher::new(),
+        }
+    }
+
+    pub(crate) fn new_keyed(key: &[u8; 32]) -> Self {
+        Self {
+            platform: Platform::detect(),
+            key_words: words_from_le_bytes(key),
+            flags: KEYED_HASH,
+            fallback: upstream_blake3::Hasher::new_keyed(key),
+        }
+    }
+
+    pub(crate) fn new_derive_key(context: &str) -> Self {
+        let context_key = upstream_blake3::hazmat::hash_derive_key_context(context);
+        Self {
+            platform: Platform::detect(),
+            key_words: words_from_le_bytes(&context_key),
+            flags: DERIVE_KEY_MATERIAL,
+            fallback: upstream_blake3::Hasher::new_derive_key(context),
+        }
+    }
+
+    /// Fill `out` with the hash of the empty input.
+    pub(crate) fn hash_empty(&self, out: &mut [u8]) {
+        self.fallback.finalize_xof().fill(out);
+    }
+
+    /// Hash each `row_len`-byte row of `rows` into the corresponding
+    /// `out_len`-byte row of `out`. Both `row_len` and `out_len` must be
+    /// nonzero, and `rows` and `out` must hold the same number of rows, at
+    /// most `BATCH_ROWS`.
+    pub(crate) fn hash_batch(&self, rows: &[u8], row_len: usize, out: &mut [u8], out_len: usize) {
+        debug_assert!(row_len > 0 && out_len > 0);
+        debug_assert_eq!(rows.len() / row_len, out.len() / out_len);
+        debug_assert!(rows.len() / row_len <= BATCH_ROWS);
+
+        if row_len > CHUNK_LEN {
+            for (row, row_out) in rows
+                .chunks_exact(row_len)
+                .zip(out.chunks_exact_mut(out_len))
+            {
+                let mut hasher = self.fallback.clone();
+                hasher.update(row);
+                hasher.finalize_xof().fill(row_out);
+            }
+            return;
+        }
+
+        let num_rows = rows.len() / row_len;
+        let mut cvs = [0u8; BATCH_ROWS * OUT_LEN];
+        let cvs = &mut cvs[..num_rows * OUT_LEN];
+        // All the blocks but the last one in each row. The last one is always
+        // nonempty.
+        let leading_len = (row_len - 1) / BLOCK_LEN * BLOCK_LEN;
+
+        // If every row is a whole number of blocks, and the caller only wants
+        // the 32-byte root hash or less, the SIMD kernel can do everything.
+        if row_len - leading_len == BLOCK_LEN && out_len <= OUT_LEN {
+            let flags_end = CHUNK_END | ROOT;
+            self.hash_many_blocks(rows, row_len, row_len, flags_end, cvs);
+            for (cv, row_out) in cvs.chunks_exact(OUT_LEN).zip(out.chunks_exact_mut(out_len)) {
+                row_out.copy_from_slice(&cv[..out_len]);
+            }
+            return;
+        }
+
+        // Otherwise, use the SIMD kernel for all the blocks but the last in
+        // each row, and then compress the last block of each row separately.
+        // That last compression is a root compression, which can produce any
+        // number of output bytes, and it can have a partial block.
+        if leading_len > 0 {
+            self.hash_many_blocks(rows, row_len, leading_len, 0, cvs);
+        }
+        let mut flags = self.flags | CHUNK_END | ROOT;
+        if leading_len == 0 {
+            flags |= CHUNK_START;
+        }
+        for (i, (row, row_out)) in rows
+            .chunks_exact(row_len)
+            .zip(out.chunks_exact_mut(out_len))
+            .enumerate()
+        {
+            let cv = if leading_len > 0 {
+                words_from_le_bytes(cvs[i * OUT_LEN..][..OUT_LEN].try_into().unwrap())
+            } else {
+                self.key_words
+            };
+            let last = &row[leading_len..];
+            let mut block = [0u8; BLOCK_LEN];
+            block[..last.len()].copy_from_slice(last);
+            for (counter, out_block) in row_out.chunks_mut(2 * OUT_LEN).enumerate() {
+                let output = self.platform.compress_xof(
+                    &cv,
+                    &block,
+                    last.len() as u8,
+                    counter as u64,
+                    flags,
+                );
+                out_block.copy_from_slice(&output[..out_block.len()]);
+            }
+        }
+    }
+
+    /// Run the SIMD kernel over the first `len` bytes of each row, writing one
+    /// chaining value (or root hash) per row into `cvs`. The kernel takes its
+    /// input length as a const parameter, so we need a match arm for every
+    /// possible number of blocks.
+    fn hash_many_blocks(
+        &self,
+        rows: &[u8],
+        row_len: usize,
+        len: usize,
+        flags_end: u8,
+        cvs: &mut [u8],
+    ) {
+        match len / BLOCK_LEN {
+            1 => self.hash_many_const::<{ BLOCK_LEN }>(rows, row_len, flags_end, cvs),
+            2 => self.hash_many_const::<{ 2 * BLOCK_LEN }>(rows, row_len, flags_end, cvs),
+            3 => self.hash_many_const::<{ 3 * BLOCK_LEN }>(rows, row_len, flags_end, cvs),
+            4 => self.hash_many_const::<{ 4 * BLOCK_LEN }>(rows, row_len, flags_end, cvs),
+            5 => self.hash_many_const::<{ 5 * BLOCK_LEN }>(rows, row_len, flags_end, cvs),
+            6 => self.hash_many_const::<{ 6 * BLOCK_LEN }>(rows, row_len, flags_end, cvs),
+            7 => self.hash_many_const::<{ 7 * BLOCK_LEN }>(rows, row_len, flags_end, cvs),
+            8 => self.hash_many_const::<{ 8 * BLOCK_LEN }>(rows, row_len, flags_end, cvs),
+            9 => self.hash_many_const::<{ 9 * BLOCK_LEN }>(rows, row_len, flags_end, cvs),
+            10 => self.hash_many_const::<{ 10 * BLOCK_LEN }>(rows, row_len, flags_end, cvs),
+            11 => self.hash_many_const::<{ 11 * BLOCK_LEN }>(rows, row_len, flags_end, cvs),
+            12 => self.hash_many_const::<{ 12 * BLOCK_LEN }>(rows, row_len, flags_end, cvs),
+            13 => self.hash_many_const::<{ 13 * BLOCK_LEN }>(rows, row_len, flags_end, cvs),
+            14 => self.hash_many_const::<{ 14 * BLOCK_LEN }>(rows, row_len, flags_end, cvs),
+            15 => self.hash_many_const::<{ 15 * BLOCK_LEN }>(rows, row_len, flags_end, cvs),
+            16 => self.hash_many_const::<{ 16 * BLOCK_LEN }>(rows, row_len, flags_end, cvs),
+            _ => unreachable!("rows are at most one chunk"),
+        }
+    }
+
+    fn hash_many_const<const N: usize>(
+        &self,
+        rows: &[u8],
+        row_len: usize,
+        flags_end: u8,
+        cvs: &mut [u8],
+    ) {
+        let mut inputs = [&[0u8; N]; BATCH_ROWS];
+        let mut num_rows = 0;
+        for (input, row) in inputs.iter_mut().zip(rows.chunks_exact(row_len)) {
+            *input = row[..N].try_into().unwrap();
+            num_rows += 1;
+        }
+        // Every row is chunk 0 of its own input, so the counter stays at 0.
+        self.platform.hash_many(
+            &inputs[..num_rows],
+            &self.key_words,
+            0,
+            IncrementCounter::No,
+            self.flags,
+            CHUNK_START,
+            flags_end,
+            cvs,
+        );
+    }
+}
+
+fn words_from_le_bytes(bytes: &[u8; 32]) -> [u32; 8] {
+    core::array::from_fn(|i| u32::from_le_bytes(bytes[4 * i..][..4].try_into().unwrap()))
+}
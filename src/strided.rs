@@ -0,0 +1,197 @@
+//! Hashing buffers that aren't contiguous, like NumPy slices and transposed
+//! arrays, or that are contiguous in a different order than the caller asked
+//! for, like Fortran-ordered arrays. We gather their bytes in logical order
+//! through a fixed-size staging buffer as we hash them, so that hashing a large
+//! array doesn't need a full copy of it. The same staging buffer is where
+//! `update_array` swaps the bytes of multi-byte items into the byte order the
+//! caller asked for.
+
+use pyo3::exceptions::PyValueError;
+use pyo3::prelude::*;
+use std::ffi::CStr;
+use std::marker::PhantomData;
+
+/// The order to read the items of a multidimensional buffer in. These are
+/// the same as the `order` argument to `memoryview.tobytes`.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Order {
+    /// Row-major, with the last index changing fastest.
+    C,
+    /// Column-major, with the first index changing fastest.
+    F,
+    /// Whichever of those the buffer is already contiguous in, or C if it's
+    /// neither.
+    A,
+}
+
+impl Order {
+    pub(crate) fn from_name(name: &str) -> PyResult<Self> {
+        match name {
+            "C" => Ok(Order::C),
+            "F" => Ok(Order::F),
+            "A" => Ok(Order::A),
+            _ => Err(PyValueError::new_err(format!(
+                "order must be \"C\", \"F\", or \"A\", not {name:?}"
+            ))),
+        }
+    }
+}
+
+/// Check that a buffer's `struct`-style format string is a single numeric
+/// type, which might start with a byte order character, and return whether
+/// its items are big-endian. We don't know how to normalize the byte order
+/// of structs or other compound types, so we reject those. Native formats
+/// (with no prefix or "@") can have platform-dependent item sizes, which we
+/// hash as they are, and the `update_array` docs warn about that.
+pub(crate) fn format_is_big_endian(format: &CStr) -> PyResult<bool> {
+    let format = format.to_bytes();
+    let (big_endian, code) = match format {
+        [b'<', rest @ ..] => (false, rest),
+        [b'>' | b'!', rest @ ..] => (true, rest),
+        [b'@' | b'=', rest @ ..] => (cfg!(target_endian = "big"), rest),
+        _ => (cfg!(target_endian = "big"), format),
+    };
+    match code {
+        [b'b' | b'B' | b'c' | b'?' | b'h' | b'H' | b'i' | b'I' | b'l' | b'L' | b'q' | b'Q']
+        | [b'n' | b'N' | b'e' | b'f' | b'd'] => Ok(big_endian),
+        _ => Err(PyValueError::new_err(format!(
+            "unsupported buffer format {:?}, expected a single numeric type",
+            String::from_utf8_lossy(format),
+        ))),
+    }
+}
+
+/// A read-only view of a buffer with arbitrary strides, which might be
+/// negative. The items are visited in C order, so for F order the caller
+/// reverses the dimensions. If `swap` is true, the bytes of each item are
+/// reversed.
+pub(crate) struct StridedBytes<'a> {
+    ptr: *const u8,
+    shape: Vec<usize>,
+    strides: Vec<isize>,
+    item_size: usize,
+    swap: bool,
+    _buffer: PhantomData<&'a [u8]>,
+}
+
+// Safety: This is a shared view of the buffer, just like a `&[u8]`. See the
+// comments in `bytes_from_pybuffer` about other threads writing to it.
+unsafe impl Send for StridedBytes<'_> {}
+unsafe impl Sync for StridedBytes<'_> {}
+
+impl StridedBytes<'_> {
+    /// The caller must guarantee that `ptr`, `shape`, `strides`, and
+    /// `item_size` describe valid memory, like they do in a `PyBuffer`
+    /// without suboffsets, and that the memory stays valid for the lifetime
+    /// of this view.
+    pub(crate) unsafe fn new(
+        ptr: *const u8,
+        shape: &[usize],
+        strides: &[isize],
+        item_size: usize,
+        order: Order,
+        swap: bool,
+    ) -> Self {
+        debug_assert_eq!(shape.len(), strides.len());
+        debug_assert!(item_size > 0);
+        let mut shape = shape.to_vec();
+        let mut strides = strides.to_vec();
+        // Visiting the dimensions in reverse C order is F order.
+        if order == Order::F {
+            shape.reverse();
+            strides.reverse();
+        }
+        Self {
+            ptr,
+            shape,
+            strides,
+            item_size,
+            swap,
+            _buffer: PhantomData,
+        }
+    }
+
+    pub(crate) fn len(&self) -> usize {
+        self.shape.iter().product::<usize>() * self.item_size
+    }
+
+    /// Pass all the bytes to `f` in order, in pieces of about `buffer_len`
+    /// bytes. Every piece is a whole number of items, and all of them but the
+    /// last are the same length.
+    pub(crate) fn for_each_piece(&self, buffer_len: usize, mut f: impl FnMut(&[u8])) {
+        let item_size = self.item_size;
+        let piece_len = (buffer_len / item_size).max(1) * item_size;
+        let mut flush = |staging: &mut Vec<u8>| {
+            if self.swap {
+                for item in staging.chunks_exact_mut(item_size) {
+                    item.reverse();
+                }
+            }
+            f(staging);
+            staging.clear();
+        };
+        if self.len() == 0 {
+            return;
+        }
+        let mut staging = Vec::with_capacity(piece_len.min(self.len()));
+        // A zero-dimensional buffer is a single item.
+        let (Some((&row_len, outer_shape)), Some((&row_stride, outer_strides))) =
+            (self.shape.split_last(), self.strides.split_last())
+        else {
+            staging.extend_from_slice(unsafe { std::slice::from_raw_parts(self.ptr, item_size) });
+            flush(&mut staging);
+            return;
+        };
+
+        let mut index = vec![0; outer_shape.len()];
+        loop {
+            let row_offset: isize = index
+                .iter()
+                .zip(outer_strides)
+                .map(|(&i, &stride)| i as isize * stride)
+                .sum();
+            // Safety: Every index is within the shape, so these offsets are
+            // within the buffer.
+            let row_ptr = unsafe { self.ptr.offset(row_offset) };
+            if row_stride == item_size as isize {
+                let mut row = unsafe { std::slice::from_raw_parts(row_ptr, row_len * item_size) };
+                while !row.is_empty() {
+                    // Both of these are whole numbers of items.
+                    let take = row.len().min(piece_len - staging.len());
+                    staging.extend_from_slice(&row[..take]);
+                    row = &row[take..];
+                    if staging.len() == piece_len {
+                        flush(&mut staging);
+                    }
+                }
+            } else {
+                for i in 0..row_len {
+                    let item_ptr = unsafe { row_ptr.offset(i as isize * row_stride) };
+                    staging.extend_from_slice(unsafe {
+                        std::slice::from_raw_parts(item_ptr, item_size)
+                    });
+                    if staging.len() == piece_len {
+                        flush(&mut staging);
+                    }
+                }
+            }
+
+            // Move to the next row, like an odometer.
+            let mut dim = index.len();
+            loop {
+                if dim == 0 {
+                    if !staging.is_empty() {
+                        flush(&mut staging);
+                    }
+                    return;
+                }
+                dim -= 1;
+                index[dim] += 1;
+                if index[dim] < outer_shape[dim] {
+                    break;
+                }
+                index[dim] = 0;
+            }
+        }
+    }
+}
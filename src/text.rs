@@ -0,0 +1,142 @@
+//! Encoding `str` input in Rust for `update_str`. UTF-8 and ASCII text can be
+//! hashed straight from the interpreter's cached UTF-8 representation. The
+//! other encodings here are written into a staging buffer a piece at a time,
+//! so hashing a long string never needs a full encoded copy of it.
+
+/// The text encodings we can do in Rust, for strings with no surrogates.
+/// Everything else goes through `str.encode`.
+#[derive(Clone, Copy)]
+pub(crate) enum TextEncoding {
+    Utf8 { bom: bool },
+    Ascii,
+    Latin1,
+    Utf16 { big_endian: bool, bom: bool },
+    Utf32 { big_endian: bool, bom: bool },
+}
+
+impl TextEncoding {
+    /// Look up a codec by the normalized name that `codecs.lookup` returns.
+    /// Like Python, the "utf-16" and "utf-32" codecs (without an explicit
+    /// byte order) write a BOM and then use the native byte order.
+    pub(crate) fn from_codec_name(name: &str) -> Option<Self> {
+        let native_big_endian = cfg!(target_endian = "big");
+        match name {
+            "utf-8" => Some(Self::Utf8 { bom: false }),
+            "utf-8-sig" => Some(Self::Utf8 { bom: true }),
+            "ascii" => Some(Self::Ascii),
+            "iso8859-1" => Some(Self::Latin1),
+            "utf-16" => Some(Self::Utf16 {
+                big_endian: native_big_endian,
+                bom: true,
+            }),
+            "utf-16-le" => Some(Self::Utf16 {
+                big_endian: false,
+                bom: false,
+            }),
+            "utf-16-be" => Some(Self::Utf16 {
+                big_endian: true,
+                bom: false,
+            }),
+            "utf-32" => Some(Self::Utf32 {
+                big_endian: native_big_endian,
+                bom: true,
+            }),
+            "utf-32-le" => Some(Self::Utf32 {
+                big_endian: false,
+                bom: false,
+            }),
+            "utf-32-be" => Some(Self::Utf32 {
+                big_endian: true,
+                bom: false,
+            }),
+            _ => None,
+        }
+    }
+
+    /// An upper bound on the encoded length of a string that's `text_len`
+    /// bytes long in UTF-8, including any BOM.
+    pub(crate) fn max_encoded_len(&self, text_len: usize) -> usize {
+        match *self {
+            Self::Utf8 { .. } | Self::Ascii | Self::Latin1 => text_len.saturating_add(3),
+            // Each byte of UTF-8 is at most two bytes of UTF-16 or four bytes
+            // of UTF-32.
+            Self::Utf16 { .. } => text_len.saturating_mul(2).saturating_add(2),
+            Self::Utf32 { .. } => text_len.saturating_mul(4).saturating_add(4),
+        }
+    }
+
+    /// Encode `text` and pass the bytes to `f` in order, in pieces of at most
+    /// `buffer_len` bytes, except that UTF-8 and ASCII are passed without
+    /// copying. If some character can't be encoded, return false without
+    /// calling `f`, and the caller should let Python raise the error or apply
+    /// its error handler.
+    pub(crate) fn encode(&self, text: &str, buffer_len: usize, mut f: impl FnMut(&[u8])) -> bool {
+        const BOM: char = '\u{feff}';
+        match *self {
+            Self::Utf8 { bom } => {
+                if bom {
+                    f(BOM.encode_utf8(&mut [0; 3]).as_bytes());
+                }
+                f(text.as_bytes());
+            }
+            Self::Ascii => {
+                if !text.is_ascii() {
+                    return false;
+                }
+                f(text.as_bytes());
+            }
+            Self::Latin1 => {
+                if text.chars().any(|c| c > '\u{ff}') {
+                    return false;
+                }
+                encode_chars(text.chars(), buffer_len, f, |c, out| out.push(c as u8));
+            }
+            Self::Utf16 { big_endian, bom } => {
+                let chars = bom.then_some(BOM).into_iter().chain(text.chars());
+                encode_chars(chars, buffer_len, f, |c, out| {
+                    for unit in c.encode_utf16(&mut [0; 2]) {
+                        if big_endian {
+                            out.extend_from_slice(&unit.to_be_bytes());
+                        } else {
+                            out.extend_from_slice(&unit.to_le_bytes());
+                        }
+                    }
+                });
+            }
+            Self::Utf32 { big_endian, bom } => {
+                let chars = bom.then_some(BOM).into_iter().chain(text.chars());
+                encode_chars(chars, buffer_len, f, |c, out| {
+                    if big_endian {
+                        out.extend_from_slice(&(c as u32).to_be_bytes());
+                    } else {
+                        out.extend_from_slice(&(c as u32).to_le_bytes());
+                    }
+                });
+            }
+        }
+        true
+    }
+}
+
+/// The most bytes any of our encodings produce for one character.
+const MAX_CHAR_LEN: usize = 4;
+
+fn encode_chars(
+    chars: impl Iterator<Item = char>,
+    buffer_len: usize,
+    mut f: impl FnMut(&[u8]),
+    mut encode_char: impl FnMut(char, &mut Vec<u8>),
+) {
+    let buffer_len = buffer_len.max(MAX_CHAR_LEN);
+    let mut staging = Vec::with_capacity(buffer_len);
+    for c in chars {
+        if staging.len() + MAX_CHAR_LEN > buffer_len {
+            f(&staging);
+            staging.clear();
+        }
+        encode_char(c, &mut staging);
+    }
+    if !staging.is_empty() {
+        f(&staging);
+    }
+}
@@ -1,15 +1,28 @@
 import array
+import base64
+import bz2
+import ctypes
 from binascii import unhexlify
+from concurrent.futures import ThreadPoolExecutor
+import gzip
+import io
 import json
 import numpy
 import os
 from pathlib import Path
+import pickle
+import signal
+import struct
 import subprocess
 import sys
+import pytest
 import tempfile
 from typing import (
     Any,
     Dict,
+    List,
+    Literal,
+    Union,
     cast,
 )
 if sys.version_info >= (3, 12):
@@ -17,10 +30,18 @@ if sys.version_info >= (3, 12):
 else:
     from typing_extensions import Buffer
 
+import blake3 as blake3_module
 from blake3 import blake3, __version__
 
 HERE = Path(__file__).parent
 
+# The C implementation in c_impl/ runs this same test suite, but it only
+# implements the core hashlib-style API. Tests for anything else get this mark.
+rust_only = pytest.mark.skipif(
+    blake3.__module__ != "blake3.blake3",
+    reason="not implemented by the C bindings",
+)
+
 VECTORS = json.load((HERE / "test_vectors.json").open())
 
 
@@ -155,22 +176,216 @@ def test_int_array_fails() -> None:
         assert False, "expected a buffer error"
 
 
-def test_strided_array_fails() -> None:
+@rust_only
+def test_update_iter() -> None:
+    # A mix of short chunks that get batched and long ones that don't.
+    chunks: List[Buffer] = [bytes([i]) * (i * 37 % 5000) for i in range(200)]
+    chunks += [bytearray(b"foo"), memoryview(b"abcdef")[::2]]
+    expected = blake3(b"".join(bytes(chunk) for chunk in chunks)).digest()
+    for max_threads in [1, 2, blake3.AUTO]:
+        hasher = blake3(max_threads=max_threads).update_iter(chunks)
+        assert hasher.digest() == expected
+        hasher = blake3(max_threads=max_threads).update_iter(iter(chunks))
+        assert hasher.digest() == expected
+
+    # If the iterator raises, the chunks before that are still hashed.
+    def generate() -> Any:
+        yield b"foo"
+        yield b"bar" * 1000
+        yield b"baz"
+        raise RuntimeError("oops")
+
+    hasher = blake3()
+    try:
+        hasher.update_iter(generate())
+        assert False, "expected a RuntimeError"
+    except RuntimeError:
+        pass
+    assert hasher.digest() == blake3(b"foo" + b"bar" * 1000 + b"baz").digest()
+
+    try:
+        blake3().update_iter([b"foo", "bar"])  # type: ignore
+        assert False, "expected a TypeError"
+    except TypeError:
+        pass
+
+
+@rust_only
+def test_update_many() -> None:
+    buffers: List[Buffer] = [b"foo", bytearray(b"bar"), memoryview(b"abcdef")[::2]]
+    buffers += [make_input(100_000), b""]
+    expected = blake3(b"".join(bytes(buffer) for buffer in buffers)).digest()
+    for max_threads in [1, 2, blake3.AUTO]:
+        hasher = blake3(max_threads=max_threads).update_many(buffers)
+        assert hasher.digest() == expected
+        hasher = blake3(max_threads=max_threads).update_many(tuple(buffers))
+        assert hasher.digest() == expected
+    assert blake3().update_many([]).digest() == blake3().digest()
+
+    # If any buffer is invalid, nothing is hashed.
+    hasher = blake3()
+    try:
+        hasher.update_many([b"foo", "bar"])  # type: ignore
+        assert False, "expected a TypeError"
+    except TypeError:
+        pass
+    assert hasher.digest() == blake3().digest()
+
+
+@rust_only
+def test_snapshot() -> None:
+    input_bytes = make_input(100_000)
+    expected = blake3(input_bytes + input_bytes[::3]).digest()
+    snapshots: List[Union[bool, Literal["detect"]]] = [False, True, "detect"]
+    for snapshot in snapshots:
+        for max_threads in [1, 2, blake3.AUTO]:
+            hasher = blake3(input_bytes, snapshot=snapshot, max_threads=max_threads)
+            hasher.update(memoryview(input_bytes)[::3])
+            assert hasher.digest() == expected
+            hasher = blake3(snapshot=snapshot, max_threads=max_threads)
+            hasher.update_many([input_bytes, memoryview(input_bytes)[::3]])
+            assert hasher.copy().digest() == expected
+
+    # Multithreaded hashers gather up short updates, and in "detect" mode they
+    # have to be able to undo them, with and without hashing what's gathered.
+    chunk = make_input(1000)
+    for max_threads in [2, blake3.AUTO]:
+        hasher = blake3(snapshot="detect", max_threads=max_threads)
+        for _ in range(3000):
+            hasher.update(chunk)
+        assert hasher.digest() == blake3(chunk * 3000).digest()
+
+    assert blake3_module.get_snapshot_default() is False
+    blake3_module.set_snapshot_default("detect")
+    try:
+        assert blake3_module.get_snapshot_default() == "detect"
+        hasher = blake3(input_bytes)
+        assert hasher.digest() == blake3(input_bytes, snapshot=False).digest()
+    finally:
+        blake3_module.set_snapshot_default(False)
+
+    try:
+        blake3(snapshot="sometimes")  # type: ignore
+        assert False, "expected a ValueError"
+    except ValueError:
+        pass
+
+
+@rust_only
+def test_update_array() -> None:
+    values = list(range(100_000))
+    little = b"".join(v.to_bytes(4, "little") for v in values)
+    big = b"".join(v.to_bytes(4, "big") for v in values)
+    native_array = array.array("I", values)
+    # ctypes can make a big-endian buffer on any platform.
+    big_uint32 = cast(Any, ctypes.c_uint32).__ctype_be__
+    big_array = memoryview((big_uint32 * len(values))(*values))
+    for data in [native_array, big_array]:
+        for max_threads in [1, 2, blake3.AUTO]:
+            hasher = blake3(max_threads=max_threads).update_array(data)
+            assert hasher.digest() == blake3(little).digest()
+            hasher = blake3(max_threads=max_threads)
+            hasher.update_array(data, byteorder="big")
+            assert hasher.digest() == blake3(big).digest()
+
+    # Strided and multidimensional arrays.
+    reversed_array = memoryview(native_array)[::-3]
+    expected = b"".join(v.to_bytes(4, "big") for v in reversed_array.tolist())
+    hasher = blake3().update_array(reversed_array, byteorder="big")
+    assert hasher.digest() == blake3(expected).digest()
+    matrix = memoryview(native_array).cast("B").cast("I", [400, 250])[::7]
+    if sys.byteorder == "little":
+        hasher = blake3().update_array(matrix, order="F")
+        assert hasher.digest() == blake3(matrix.tobytes("F")).digest()
+
+    # Floats and bytes.
+    floats = array.array("d", [1.5, -2.0])
+    hasher = blake3().update_array(floats, byteorder="big")
+    assert hasher.digest() == blake3(struct.pack(">2d", 1.5, -2.0)).digest()
+    assert blake3().update_array(b"foo").digest() == blake3(b"foo").digest()
+
+    # Structs aren't supported.
+    class Point(ctypes.Structure):
+        _fields_ = [("x", ctypes.c_int), ("y", ctypes.c_int)]
+
+    for data, byteorder in [(memoryview((Point * 2)()), "little"), (b"", "native")]:
+        try:
+            blake3().update_array(data, byteorder=byteorder)  # type: ignore
+            assert False, "expected a ValueError"
+        except ValueError:
+            pass
+
+
+def test_strided_array() -> None:
     unstrided = numpy.array([1, 2, 3, 4], numpy.uint8)
     strided = numpy.lib.stride_tricks.as_strided(unstrided, shape=[2], strides=[2])
     assert bytes(strided) == bytes([1, 3])
     # Unstrided works fine.
     blake3(cast(Buffer, unstrided))
+    if blake3.__module__ == "blake3.blake3":
+        # Strided arrays work in Rust.
+        assert blake3(cast(Buffer, strided)).digest() == blake3(b"\x01\x03").digest()
+        # So do Fortran-ordered ones, in either order.
+        matrix = numpy.arange(12, dtype=numpy.uint8).reshape(3, 4)
+        fortran = numpy.asfortranarray(matrix)
+        orders: List[Literal["C", "F", "A"]] = ["C", "F", "A"]
+        for order in orders:
+            expected = blake3(fortran.tobytes(order=order)).digest()
+            assert blake3(cast(Buffer, fortran), order=order).digest() == expected
+        assert blake3(cast(Buffer, matrix[:, ::2])).digest() == blake3(
+            matrix[:, ::2].tobytes()
+        ).digest()
+        return
     try:
-        # But strided fails.
+        # But strided fails in C.
         blake3(cast(Buffer, strided))
-    # We get BufferError in Rust and ValueError in C.
-    except (BufferError, ValueError):
+    except ValueError:
         pass
     else:
         assert False, "expected a buffer error"
 
 
+@rust_only
+def test_update_order() -> None:
+    input_bytes = bytes(make_input(300_000))
+    matrix = memoryview(input_bytes).cast("B", [600, 500])
+    views = [
+        matrix,
+        matrix[::3],
+        matrix[::-1],
+        memoryview(input_bytes)[::7],
+        memoryview(input_bytes)[10:10],
+    ]
+    orders: List[Literal["C", "F", "A"]] = ["C", "F", "A"]
+    for view in views:
+        for order in orders:
+            expected = blake3(view.tobytes(order)).digest()
+            for max_threads in [1, 2, blake3.AUTO]:
+                hasher = blake3(view, order=order, max_threads=max_threads)
+                assert hasher.digest() == expected
+                hasher = blake3(max_threads=max_threads)
+                assert hasher.update(view, order=order).digest() == expected
+
+    try:
+        blake3(b"foo", order="X")  # type: ignore
+        assert False, "expected a ValueError"
+    except ValueError:
+        pass
+
+
+def test_bytes_subclass() -> None:
+    # Exact bytes objects take a fast path. Subclasses should hash the same.
+    class MyBytes(bytes):
+        pass
+
+    for length in [0, 3, 10_000]:
+        data = bytes(make_input(length))
+        expected = blake3(memoryview(data)).digest()
+        assert blake3(data).digest() == expected
+        assert blake3(MyBytes(data)).digest() == expected
+        assert blake3().update(MyBytes(data)).digest() == expected
+
+
 def test_string_fails() -> None:
     try:
         blake3("a string")  # type: ignore
@@ -180,6 +395,44 @@ def test_string_fails() -> None:
         assert False, "expected a type error"
 
 
+@rust_only
+def test_update_str() -> None:
+    strings = ["", "foo", "h\xe9llo", "\xff" * 3000, "a\U0001f600b" * 10_000]
+    encodings = ["utf-8", "UTF8", "ascii", "latin-1", "utf-16", "utf-16-be"]
+    # cp1252 isn't implemented in Rust.
+    encodings += ["utf-32-le", "cp1252"]
+    for s in strings:
+        for encoding in encodings:
+            for errors in ["strict", "replace"]:
+                try:
+                    expected = blake3(s.encode(encoding, errors)).digest()
+                except UnicodeEncodeError:
+                    try:
+                        blake3().update_str(s, encoding, errors)
+                        assert False, "expected a UnicodeEncodeError"
+                    except UnicodeEncodeError:
+                        pass
+                    continue
+                for max_threads in [1, blake3.AUTO]:
+                    hasher = blake3(max_threads=max_threads)
+                    assert hasher.update_str(s, encoding, errors).digest() == expected
+
+    # Surrogates fall back to Python too.
+    s = "foo\ud800"
+    expected = blake3(s.encode("utf-8", "surrogatepass")).digest()
+    assert blake3().update_str(s, errors="surrogatepass").digest() == expected
+    try:
+        blake3().update_str(s)
+        assert False, "expected a UnicodeEncodeError"
+    except UnicodeEncodeError:
+        pass
+    try:
+        blake3().update_str("foo", "not-an-encoding")
+        assert False, "expected a LookupError"
+    except LookupError:
+        pass
+
+
 def test_constants() -> None:
     # These are class attributes, so they should work on the class itself and
     # also on instances of the class.
@@ -292,6 +545,33 @@ def test_copy_with_threads() -> None:
     ), "Update state of copy diverged from expected state"
 
 
+def test_small_updates_with_threads() -> None:
+    # Multithreaded hashers gather up short updates internally. Mix short and
+    # long updates, and check that reading the hasher in between sees all of
+    # them.
+    b = make_input(3 * 10**6)
+    lengths = [1, 4096, 100, 2 * 10**6, 4096, 10**5]
+    for max_threads in [2, blake3.AUTO]:
+        hasher = blake3(max_threads=max_threads)
+        all_input = bytearray()
+        position = 0
+        for length in lengths * 5:
+            chunk = b[position : position + length]
+            position = (position + length) % 10**6
+            hasher.update(chunk)
+            all_input += chunk
+            copy = hasher.copy()
+            assert copy.digest() == hasher.digest()
+        expected = hasher.digest()
+        assert expected == blake3(all_input).digest()
+        hasher.update(b"foo")
+        assert copy.digest() == expected
+        copy.update(b"foo")
+        assert copy.digest() == hasher.digest()
+        hasher.reset()
+        assert hasher.update(b"foo").digest() == blake3(b"foo").digest()
+
+
 def test_version() -> None:
     # Just sanity check that it's a version string. Don't assert the specific
     # version, both because we don't want to bother with parsing Cargo.toml,
@@ -467,3 +747,1005 @@ def test_mmap() -> None:
         assert False, "expected a file not found error"
     except FileNotFoundError:
         pass
+
+
+@rust_only
+def test_mmap_range() -> None:
+    input_bytes = make_input(1_000_000)
+    (fd, temp_path) = tempfile.mkstemp()
+    os.close(fd)
+    with open(temp_path, "wb") as f:
+        f.write(input_bytes)
+
+    n = len(input_bytes)
+    # Cover ranges that are mapped and ranges that are short enough to read.
+    ranges = [(None, 100), (5, None), (1000, 20_000), (123_457, 800_000), (n, 0)]
+    for max_threads in [1, 2, blake3.AUTO]:
+        for offset, length in ranges:
+            start = offset or 0
+            end = n if length is None else start + length
+            expected = blake3(input_bytes[start:end]).digest()
+            hasher = blake3(max_threads=max_threads)
+            hasher.update_mmap(temp_path, offset=offset, length=length)
+            assert hasher.digest() == expected
+            hasher = blake3(max_threads=max_threads)
+            hasher.update_path(temp_path, offset=offset, length=length)
+            assert hasher.digest() == expected
+            assert expected == blake3_module.hash_file(
+                temp_path, offset=offset, size=length, max_threads=max_threads
+            )
+
+    for offset, length in [(n + 1, None), (0, n + 1), (n - 5, 6)]:
+        try:
+            blake3().update_mmap(temp_path, offset=offset, length=length)
+            assert False, "expected a ValueError"
+        except ValueError:
+            pass
+        try:
+            blake3().update_path(temp_path, offset=offset, length=length)
+            assert False, "expected a ValueError"
+        except ValueError:
+            pass
+    try:
+        blake3_module.hash_file(io.BytesIO(b"foo"), offset=1)
+        assert False, "expected a ValueError"
+    except ValueError:
+        pass
+    os.remove(temp_path)
+
+
+@rust_only
+def test_update_path() -> None:
+    input_bytes = make_input(1_000_000)
+    with tempfile.TemporaryDirectory() as temp_dir:
+        path = Path(temp_dir) / "file"
+        path.write_bytes(input_bytes)
+        for max_threads in [1, 2, blake3.AUTO]:
+            hasher = blake3(max_threads=max_threads).update_path(path)
+            assert hasher.digest() == blake3(input_bytes).digest()
+
+        # A failed read leaves the hasher unchanged.
+        hasher = blake3(b"foo")
+        try:
+            hasher.update_path(Path(temp_dir) / "missing")
+            assert False, "expected a FileNotFoundError"
+        except FileNotFoundError:
+            pass
+        assert hasher.digest() == blake3(b"foo").digest()
+
+
+@rust_only
+def test_update_file() -> None:
+    # Big enough to be memory mapped, when it's a real file.
+    input_bytes = make_input(1_000_000)
+    (fd, temp_path) = tempfile.mkstemp()
+    os.close(fd)
+    with open(temp_path, "wb") as f:
+        f.write(input_bytes)
+
+    for max_threads in [1, 2, blake3.AUTO]:
+        # Start from the middle of the file, after Python has buffered some of
+        # it, and check that we end up at the end.
+        with open(temp_path, "rb") as f:
+            f.read(10)
+            hasher = blake3(max_threads=max_threads).update_file(f)
+            assert hasher.digest() == blake3(input_bytes[10:]).digest()
+            assert f.read() == b""
+        # Unbuffered, with small reads
+        with open(temp_path, "rb", buffering=0) as f:
+            f.seek(len(input_bytes) - 100)
+            hasher = blake3(max_threads=max_threads).update_file(f, buffer_size=7)
+            assert hasher.digest() == blake3(input_bytes[-100:]).digest()
+        # Not a real file
+        hasher = blake3(max_threads=max_threads)
+        hasher.update(b"foo").update_file(io.BytesIO(input_bytes), buffer_size=1000)
+        assert hasher.digest() == blake3(b"foo" + input_bytes).digest()
+    os.remove(temp_path)
+
+    # A readinto that doesn't fill the buffer, and that keeps a reference to it.
+    class Reader:
+        def __init__(self) -> None:
+            self.count = 0
+
+        def readinto(self, buffer: Any) -> int:
+            self.kept = memoryview(buffer)
+            self.count += 1
+            if self.count > 2:
+                return 0
+            self.kept[:3] = b"abc"
+            return 3
+
+    reader = Reader()
+    assert blake3().update_file(reader).digest() == blake3(b"abcabc").digest()
+    assert bytes(reader.kept[:3]) == b"abc"
+
+    try:
+        blake3().update_file(io.StringIO("foo"))  # type: ignore
+        assert False, "expected a TypeError"
+    except TypeError:
+        pass
+    try:
+        blake3().update_file(io.BytesIO(b"foo"), buffer_size=0)
+        assert False, "expected a ValueError"
+    except ValueError:
+        pass
+
+
+@rust_only
+def test_update_fd() -> None:
+    input_bytes = make_input(1_000_000)
+
+    # A pipe from a subprocess
+    script = "import sys; sys.stdout.buffer.write((bytes(range(251)) * 4000)[:10**6])"
+    command = [sys.executable, "-c", script]
+    for max_threads in [1, 2, blake3.AUTO]:
+        with subprocess.Popen(command, stdout=subprocess.PIPE) as p:
+            assert p.stdout is not None
+            hasher = blake3(max_threads=max_threads).update_fd(p.stdout.fileno())
+        assert hasher.digest() == blake3(input_bytes).digest()
+    with subprocess.Popen(command, stdout=subprocess.PIPE) as p:
+        assert p.stdout is not None
+        hasher = blake3().update_fd(p.stdout.fileno(), length=12345)
+        p.stdout.close()
+    assert hasher.digest() == blake3(input_bytes[:12345]).digest()
+
+    # A regular file, with and without offsets
+    (fd, temp_path) = tempfile.mkstemp()
+    try:
+        os.write(fd, input_bytes)
+        os.lseek(fd, 7, os.SEEK_SET)
+        hasher = blake3().update_fd(fd, length=100)
+        assert hasher.digest() == blake3(input_bytes[7:107]).digest()
+        assert os.lseek(fd, 0, os.SEEK_CUR) == 107
+        hasher = blake3().update_fd(fd, offset=500_000, length=100_000)
+        assert hasher.digest() == blake3(input_bytes[500_000:600_000]).digest()
+        hasher = blake3().update_fd(fd, offset=999_000)
+        assert hasher.digest() == blake3(input_bytes[999_000:]).digest()
+        hasher = blake3().update_fd(fd, offset=2_000_000)
+        assert hasher.digest() == blake3().digest()
+    finally:
+        os.close(fd)
+        os.remove(temp_path)
+
+    try:
+        blake3().update_fd(-1)
+        assert False, "expected an error"
+    except (ValueError, OSError):
+        pass
+
+    # If a signal handler raises partway through, the hasher is unchanged.
+    if hasattr(signal, "setitimer"):
+
+        class Alarm(Exception):
+            pass
+
+        def raise_alarm(signum: int, frame: Any) -> None:
+            raise Alarm()
+
+        (read_fd, write_fd) = os.pipe()
+        old_handler = signal.signal(signal.SIGALRM, raise_alarm)
+        try:
+            os.write(write_fd, b"bar")
+            hasher = blake3(b"foo")
+            signal.setitimer(signal.ITIMER_REAL, 0.1)
+            try:
+                hasher.update_fd(read_fd)
+                assert False, "expected an Alarm"
+            except Alarm:
+                pass
+            assert hasher.digest() == blake3(b"foo").digest()
+        finally:
+            signal.setitimer(signal.ITIMER_REAL, 0)
+            signal.signal(signal.SIGALRM, old_handler)
+            os.close(read_fd)
+            os.close(write_fd)
+
+
+@rust_only
+def test_hash_file() -> None:
+    # Big enough that upstream memory maps it.
+    input_bytes = make_input(1_000_000)
+    (fd, temp_path) = tempfile.mkstemp()
+    os.close(fd)
+    with open(temp_path, "wb") as f:
+        f.write(input_bytes)
+    key = bytes(range(32))
+
+    for max_threads in [1, 2, blake3.AUTO]:
+        assert blake3_module.hash_file(
+            temp_path, max_threads=max_threads
+        ) == blake3(input_bytes).digest()
+        assert blake3_module.hash_file(
+            Path(temp_path), key=key, length=100, max_threads=max_threads
+        ) == blake3(input_bytes, key=key).digest(100)
+        # File objects are read from their current position.
+        with open(temp_path, "rb") as f:
+            f.seek(10)
+            assert blake3_module.hash_file(
+                f, derive_key_context="foo", max_threads=max_threads
+            ) == blake3(input_bytes[10:], derive_key_context="foo").digest()
+    assert blake3_module.hash_file(io.BytesIO(b"foo")) == blake3(b"foo").digest()
+    assert blake3_module.hash_file(io.BytesIO()) == blake3().digest()
+    # Return a Hash instead of bytes.
+    file_hash = blake3_module.hash_file(temp_path, as_hash=True)
+    assert isinstance(file_hash, blake3_module.Hash)
+    assert file_hash == blake3(input_bytes).finalize()
+    keyed_hash = blake3_module.hash_file(io.BytesIO(b"foo"), key=key, as_hash=True)
+    assert keyed_hash == blake3(b"foo", key=key).digest()
+    try:
+        blake3_module.hash_file(temp_path, length=31, as_hash=True)  # type: ignore
+        assert False, "expected a ValueError"
+    except ValueError:
+        pass
+
+    # Compressed file objects have the fileno of the compressed file, but they
+    # return decompressed bytes. These need to be read, not memory mapped.
+    # Random bytes don't compress, so the compressed files are big enough to
+    # memory map.
+    random_bytes = os.urandom(1_000_000)
+    for module in [gzip, bz2]:
+        with open(temp_path, "wb") as f:
+            f.write(module.compress(random_bytes))
+        with module.open(temp_path, "rb") as compressed:
+            assert blake3_module.hash_file(compressed) == blake3(random_bytes).digest()
+        with module.open(temp_path, "rb") as compressed:
+            hasher = blake3().update_file(compressed)
+            assert hasher.digest() == blake3(random_bytes).digest()
+    os.remove(temp_path)
+
+    try:
+        blake3_module.hash_file("/non/existent/file.txt")
+        assert False, "expected a file not found error"
+    except FileNotFoundError:
+        pass
+    try:
+        blake3_module.hash_file(io.StringIO("foo"))  # type: ignore
+        assert False, "expected a TypeError"
+    except TypeError:
+        pass
+
+
+@rust_only
+def test_hash_files() -> None:
+    with tempfile.TemporaryDirectory() as temp_dir:
+        root = Path(temp_dir)
+        contents = {
+            "a.txt": b"a",
+            "b.py": b"b",
+            "sub/c.py": b"c",
+            "sub/deeper/d.py": b"d" * 100_000,
+            ".git/config": b"e",
+        }
+        for name, data in contents.items():
+            (root / name).parent.mkdir(parents=True, exist_ok=True)
+            (root / name).write_bytes(data)
+        expected = {
+            root / name: blake3(data).digest() for name, data in contents.items()
+        }
+
+        for max_threads in [1, 2, blake3.AUTO]:
+            hashes = blake3_module.hash_files(temp_dir, max_threads=max_threads)
+            assert hashes == expected
+        # Directories are walked in sorted order.
+        assert list(blake3_module.hash_files([root])) == sorted(expected)
+        assert blake3_module.hash_files(
+            [root / "a.txt", root / "sub"], length=7
+        ) == {
+            root / name: blake3(data).digest(7)
+            for name, data in contents.items()
+            if name == "a.txt" or name.startswith("sub/")
+        }
+        assert dict(blake3_module.hash_files(root, stream=True)) == expected
+
+        # Globs
+        assert set(blake3_module.hash_files(root, include=["*.py"])) == {
+            root / "b.py",
+            root / "sub/c.py",
+            root / "sub/deeper/d.py",
+        }
+        assert set(
+            blake3_module.hash_files(root, include=["sub/**/*.py"], exclude=["deeper"])
+        ) == {root / "sub/c.py"}
+        assert set(blake3_module.hash_files(root, exclude=[".*", "[ab].*"])) == {
+            root / "sub/c.py",
+            root / "sub/deeper/d.py",
+        }
+
+        # Errors
+        missing = root / "missing"
+        try:
+            blake3_module.hash_files([root, missing])
+            assert False, "expected a file not found error"
+        except FileNotFoundError as e:
+            assert e.filename == str(missing)
+        assert blake3_module.hash_files([root, missing], on_error="skip") == expected
+        with_errors = blake3_module.hash_files([missing], on_error="return")
+        assert isinstance(with_errors[missing], FileNotFoundError)
+        stream = blake3_module.hash_files(missing, on_error="skip", stream=True)
+        assert list(stream) == []
+        for kwargs in [{"on_error": "foo"}, {"include": ["[a"]}, {"exclude": [""]}]:
+            try:
+                blake3_module.hash_files(root, **kwargs)  # type: ignore
+                assert False, "expected a ValueError"
+            except ValueError:
+                pass
+        # Huge lengths raise instead of crashing.
+        for huge_length in [2**61, sys.maxsize + 1]:
+            try:
+                blake3_module.hash_files(root, length=huge_length)
+                assert False, "should throw"
+            except (OverflowError, MemoryError):
+                pass
+            try:
+                list(blake3_module.hash_files(root, length=huge_length, stream=True))
+                assert False, "should throw"
+            except (OverflowError, MemoryError):
+                pass
+        # Running out of memory isn't a file error, so it isn't skipped.
+        for call in [
+            lambda: blake3_module.hash_files(root, length=2**61, on_error="skip"),
+            lambda: blake3_module.hash_files(root, length=2**61, on_error="return"),
+        ]:
+            try:
+                call()
+                assert False, "expected a MemoryError"
+            except MemoryError:
+                pass
+
+
+@rust_only
+def test_checksum_lines_and_check() -> None:
+    with tempfile.TemporaryDirectory() as temp_dir:
+        root = Path(temp_dir)
+        (root / "foo").write_bytes(b"foo")
+        (root / "bar").write_bytes(b"bar")
+        paths = [root / "foo", root / "bar"]
+        lines = blake3_module.checksum_lines(paths)
+        # Like b3sum, paths always use forward slashes.
+        foo_path = (root / "foo").as_posix()
+        bar_path = (root / "bar").as_posix()
+        assert lines == [
+            f"{blake3(b'foo').hexdigest()}  {foo_path}\n",
+            f"{blake3(b'bar').hexdigest()}  {bar_path}\n",
+        ]
+        short_lines = blake3_module.checksum_lines(paths, length=8, max_threads=1)
+        assert short_lines[0] == f"{blake3(b'foo').hexdigest(8)}  {foo_path}\n"
+        for huge_length in [2**61, sys.maxsize + 1]:
+            try:
+                blake3_module.checksum_lines(paths, length=huge_length)
+                assert False, "should throw"
+            except (OverflowError, MemoryError):
+                pass
+
+        checkfile = root / "sums.b3"
+        with open(checkfile, "w") as f:
+            f.writelines(lines)
+        results = blake3_module.check(checkfile)
+        assert [(r.path, r.status, r.ok) for r in results] == [
+            (foo_path, "OK", True),
+            (bar_path, "OK", True),
+        ]
+
+        # Mixed lengths, a modified file, and a missing file.
+        (root / "bar").write_bytes(b"baz")
+        missing = (root / "missing").as_posix()
+        text = "".join(short_lines) + lines[1] + f"{'00' * 32}  {missing}\n"
+        results = blake3_module.check(io.StringIO(text), max_threads=1)
+        assert [r.status for r in results] == ["OK", "FAILED", "FAILED", "MISSING"]
+        assert results[1].error is None
+        assert isinstance(results[3].error, FileNotFoundError)
+
+    # Escaping, as in b3sum.
+    hash_hex = blake3(b"").hexdigest()
+    for line, path in [
+        (f"{hash_hex}  two  spaces", "two  spaces"),
+        (f"\\{hash_hex}  a\\nb\\r", "a\nb\r"),
+    ]:
+        [result] = blake3_module.check(io.BytesIO(line.encode()))
+        assert result.path == path
+        assert not result.ok
+    for line in ["", "foo", f"{hash_hex} foo", f"{hash_hex.upper()}  foo", "\\00  \\x"]:
+        try:
+            blake3_module.check(io.StringIO(line + "\n"))
+            assert False, "expected a ValueError"
+        except ValueError:
+            pass
+
+
+@rust_only
+def test_output_reader() -> None:
+    hasher = blake3(b"foo")
+    expected = hasher.digest(length=10_000)
+
+    reader = hasher.finalize_xof()
+    assert reader.tell() == 0
+    assert reader.read(100) == expected[:100]
+    assert reader.tell() == 100
+    buf = bytearray(5_000)
+    assert reader.readinto(buf) == 5_000
+    assert buf == expected[100:5_100]
+    assert reader.read(0) == b""
+    assert reader.tell() == 5_100
+
+    # Seeking works relative to the start and to the current position.
+    assert reader.seek(42) == 42
+    assert reader.read(10) == expected[42:52]
+    assert reader.seek(-2, os.SEEK_CUR) == 50
+    assert reader.read(10) == expected[50:60]
+    assert reader.seek(2**64 - 1) == 2**64 - 1
+    for bad_position in [-1, 2**64]:
+        try:
+            reader.seek(bad_position)
+            assert False, "expected a ValueError"
+        except ValueError:
+            pass
+    try:
+        reader.seek(0, os.SEEK_END)
+        assert False, "expected io.UnsupportedOperation"
+    except io.UnsupportedOperation:
+        pass
+
+    # A read that can't allocate its output doesn't move the position.
+    position = reader.tell()
+    try:
+        reader.read(2**62)
+        assert False, "expected a MemoryError"
+    except MemoryError:
+        pass
+    assert reader.tell() == position
+
+    # The stream is unbounded, so read() needs a size.
+    try:
+        reader.read()
+        assert False, "expected io.UnsupportedOperation"
+    except io.UnsupportedOperation:
+        pass
+
+    # Writing to a read-only buffer fails.
+    try:
+        reader.readinto(b"foo")
+        assert False, "expected a BufferError"
+    except BufferError:
+        pass
+
+    # The reader doesn't change if the hasher changes.
+    reader.seek(0)
+    hasher.update(b"bar")
+    assert reader.read(32) == expected[:32]
+
+    # Closing.
+    assert not reader.closed
+    reader.close()
+    assert reader.closed
+    reader.close()
+    try:
+        reader.read(1)
+        assert False, "expected a ValueError"
+    except ValueError:
+        pass
+    with hasher.finalize_xof() as reader2:
+        assert reader2.read(32) == blake3(b"foobar").digest()
+    assert reader2.closed
+
+
+@rust_only
+def test_output_reader_io() -> None:
+    expected = blake3(b"foo").digest(length=100_000)
+    reader = blake3(b"foo").finalize_xof()
+    assert isinstance(reader, blake3_module.OutputReader)
+    assert isinstance(reader, io.RawIOBase)
+    assert reader.readable()
+    assert reader.seekable()
+    assert not reader.writable()
+    buffered = io.BufferedReader(reader)  # type: ignore
+    assert buffered.read(7) == expected[:7]
+    assert buffered.read(99_993) == expected[7:]
+    buffered.seek(3)
+    assert buffered.read(10) == expected[3:13]
+
+    # The rest of the io.RawIOBase interface.
+    assert not reader.isatty()
+    reader.flush()
+    try:
+        reader.fileno()
+        assert False, "expected io.UnsupportedOperation"
+    except io.UnsupportedOperation:
+        pass
+    reader.seek(0)
+    line = reader.readline()
+    assert line.endswith(b"\n") and b"\n" not in line[:-1]
+    assert line == expected[: len(line)]
+    assert reader.readline(3) == expected[len(line) : len(line) + 3]
+    reader.seek(0)
+    lines = reader.readlines(1000)
+    assert sum(map(len, lines)) >= 1000
+    assert b"".join(lines) == expected[: reader.tell()]
+    try:
+        reader.readlines()
+        assert False, "expected io.UnsupportedOperation"
+    except io.UnsupportedOperation:
+        pass
+    reader.seek(0)
+    assert next(iter(reader)) == line
+
+    # Buffered readers work as context managers, which flushes and closes the
+    # raw reader on the way out.
+    with io.BufferedReader(blake3(b"foo").finalize_xof()) as b:  # type: ignore
+        assert b.readline() == line
+        assert b.read(100) == expected[len(line) : len(line) + 100]
+    assert b.closed
+
+
+@rust_only
+def test_output_reader_threads() -> None:
+    # Long reads release the GIL. Reading from one reader on several threads
+    # at once shouldn't deadlock, and each read gets its own part of the
+    # output stream.
+    reader = blake3(b"foo").finalize_xof()
+    sizes = [2**16, 10] * 20
+    with ThreadPoolExecutor(4) as pool:
+        outputs = list(pool.map(reader.read, sizes))
+    assert reader.tell() == sum(sizes)
+    expected = blake3(b"foo").digest(length=sum(sizes))
+    outputs.sort(key=expected.index)
+    assert b"".join(outputs) == expected
+
+
+@rust_only
+def test_digest_into() -> None:
+    hasher = blake3(b"foo")
+    expected = hasher.digest(length=10_000)
+    for length in [0, 1, 32, 100, 10_000]:
+        for seek in [0, 1, 1000]:
+            buf = bytearray(length)
+            hasher.digest_into(buf, seek=seek)
+            assert buf == hasher.digest(length, seek=seek)
+
+            hexbuf = bytearray(2 * length)
+            hasher.hexdigest_into(hexbuf, seek=seek)
+            assert hexbuf.decode() == hasher.hexdigest(length, seek=seek)
+
+    # Other writable buffer types work too.
+    array_buf = array.array("b", bytes(100))
+    hasher.digest_into(array_buf)
+    assert array_buf.tobytes() == expected[:100]
+    backing = bytearray(200)
+    hasher.digest_into(memoryview(backing)[50:150], seek=50)
+    assert backing[50:150] == expected[50:150]
+    assert backing[:50] == backing[150:] == bytes(50)
+
+    # Read-only buffers fail.
+    for readonly in [b"foo", memoryview(bytearray(32)).toreadonly()]:
+        try:
+            hasher.digest_into(readonly)
+            assert False, "expected a BufferError"
+        except BufferError:
+            pass
+
+    # Hex output needs an even length.
+    try:
+        hasher.hexdigest_into(bytearray(3))
+        assert False, "expected a ValueError"
+    except ValueError:
+        pass
+
+
+def test_multithreaded_xof() -> None:
+    # Long outputs from multithreaded hashers are split up across threads.
+    # They should match single-threaded outputs exactly, including at odd
+    # lengths and offsets.
+    expected_hasher = blake3(b"foo")
+    for max_threads in [2, blake3.AUTO]:
+        hasher = blake3(b"foo", max_threads=max_threads)
+        for length in [0, 1, 1_000_000, 1_000_003]:
+            for seek in [0, 1, 63, 64, 65_537, 2**64 - 1_000_000]:
+                expected = expected_hasher.digest(length, seek=seek)
+                assert expected == hasher.digest(length, seek=seek)
+
+
+@rust_only
+def test_hash_type() -> None:
+    hasher = blake3(b"foo")
+    h = hasher.finalize()
+    assert isinstance(h, blake3_module.Hash)
+    digest = hasher.digest()
+    assert bytes(h) == digest
+    assert h.to_hex() == hasher.hexdigest()
+    assert str(h) == hasher.hexdigest()
+    assert repr(h) == f"blake3.Hash.from_hex('{hasher.hexdigest()}')"
+
+    # Equality works with other hashes and with buffers.
+    assert h == hasher.finalize()
+    assert h == blake3_module.Hash(digest)
+    assert h == digest
+    assert digest == h
+    assert h == bytearray(digest)
+    assert h == memoryview(digest)
+    assert h != blake3(b"bar").finalize()
+    assert h != digest[:31]
+    assert h != digest + b"\0"
+    assert h != hasher.hexdigest()
+    assert h != 42
+    # Buffers that aren't contiguous compare unequal instead of raising.
+    assert blake3_module.Hash(bytes(32)) != memoryview(bytes(64))[::2]
+    assert not blake3_module.Hash(bytes(32)) == memoryview(bytes(64))[::2]
+
+    # Hashing is consistent with equality to bytes.
+    assert hash(h) == hash(digest)
+    assert {h: 1}[digest] == 1
+    assert len({h, hasher.finalize(), digest}) == 1
+
+    # Hex parsing accepts either case and rejects anything else.
+    assert h == blake3_module.Hash.from_hex(hasher.hexdigest().upper())
+    for bad_hex in ["", "ab", hasher.hexdigest()[:-1] + "g", hasher.hexdigest() + "00"]:
+        try:
+            blake3_module.Hash.from_hex(bad_hex)
+            assert False, "expected a ValueError"
+        except ValueError:
+            pass
+    for bad_bytes in [b"", digest[:31], digest + b"\0"]:
+        try:
+            blake3_module.Hash(bad_bytes)
+            assert False, "expected a ValueError"
+        except ValueError:
+            pass
+
+    assert h == pickle.loads(pickle.dumps(h))
+
+
+@rust_only
+def test_verify() -> None:
+    key = bytes(range(32))
+    hasher = blake3(b"foo", key=key)
+    hasher.verify(hasher.digest())
+    hasher.verify(bytearray(hasher.digest(100)))
+    hasher.verify(memoryview(hasher.digest(100, seek=5)), seek=5)
+    hasher.verify(hasher.digest(1))
+
+    bad_outputs = [
+        blake3(b"foo").digest(),
+        hasher.digest()[:-1] + b"\0",
+        hasher.digest(10, seek=1),
+    ]
+    for bad_output in bad_outputs:
+        try:
+            hasher.verify(bad_output)
+            assert False, "expected a VerificationError"
+        except blake3_module.VerificationError:
+            pass
+    assert issubclass(blake3_module.VerificationError, ValueError)
+
+    # Empty outputs would match anything, so they're rejected.
+    try:
+        hasher.verify(b"")
+        assert False, "expected a ValueError"
+    except blake3_module.VerificationError:
+        assert False, "expected a plain ValueError"
+    except ValueError:
+        pass
+
+
+@rust_only
+def test_compare_digest() -> None:
+    compare_digest = blake3_module.compare_digest
+    assert compare_digest(b"", b"")
+    assert compare_digest(b"foo", b"foo")
+    assert compare_digest(b"foo", bytearray(b"foo"))
+    assert compare_digest(memoryview(b"foo"), array.array("b", b"foo"))
+    assert not compare_digest(b"foo", b"fob")
+    assert not compare_digest(b"foo", b"fo")
+    assert not compare_digest(b"foo", b"")
+    try:
+        compare_digest("foo", "foo")  # type: ignore
+        assert False, "expected a TypeError"
+    except TypeError:
+        pass
+
+
+@rust_only
+def test_encoded_digest() -> None:
+    hasher = blake3(b"foo")
+    for length in [0, 1, 2, 3, 4, 5, 32, 100]:
+        for seek in [0, 7]:
+            digest = hasher.digest(length, seek=seek)
+
+            def check(encoding: str, expected: str) -> None:
+                assert hasher.encoded_digest(encoding, length, seek=seek) == expected
+                assert blake3_module.encode_digest(digest, encoding) == expected
+                assert blake3_module.decode_digest(expected, encoding) == digest
+
+            check("hex", digest.hex())
+            check("base64", base64.b64encode(digest).decode())
+            check("base64url", base64.urlsafe_b64encode(digest).decode().rstrip("="))
+            check("base32", base64.b32encode(digest).decode().rstrip("=").lower())
+            upper_hex = hasher.encoded_digest(
+                "hex", length, seek=seek, uppercase=True, sep=":"
+            )
+            assert upper_hex == digest.hex(":").upper()
+            upper_base32 = hasher.encoded_digest(
+                "base32", length, seek=seek, uppercase=True
+            )
+            assert upper_base32 == base64.b32encode(digest).decode().rstrip("=")
+            for encoding in ["base32crockford", "base58"]:
+                encoded = hasher.encoded_digest(encoding, length, seek=seek)
+                assert blake3_module.decode_digest(encoded, encoding) == digest
+            if length % 4 == 0:
+                encoded = hasher.encoded_digest("z85", length, seek=seek)
+                assert blake3_module.decode_digest(encoded, "z85") == digest
+
+    # Known answers.
+    encode = blake3_module.encode_digest
+    decode = blake3_module.decode_digest
+    assert encode(b"hello world", "base58") == "StV1DL6CwTryKyV"
+    assert encode(bytes.fromhex("864FD26FB559F75B"), "z85") == "HelloWorld"
+    assert encode(b"\x00" * 5, "base32crockford") == "00000000"
+    assert encode(b"\xff" * 5, "base32crockford", uppercase=True) == "ZZZZZZZZ"
+    assert encode(bytes([1, 35, 69]), "hex", sep="-") == "01-23-45"
+    # Only the digits change case, not the separator, like bytes.hex.
+    assert encode(b"\x01\x02\xab", "hex", sep="X") == b"\x01\x02\xab".hex("X")
+    assert encode(b"\x01\x02\xab", "hex", sep="x", uppercase=True) == "01x02xAB"
+
+    # Case-insensitive decoding, Crockford substitutions, and separators.
+    assert decode("ABCDEF", "hex") == bytes.fromhex("abcdef")
+    assert decode("MZXW6", "base32") == decode("mzxw6", "base32") == b"foo"
+    crockford_substituted = decode("0O1-Il000", "base32crockford")
+    assert crockford_substituted == decode("00111000", "base32crockford")
+    assert decode("01:23:45", "hex", sep=":") == bytes([1, 35, 69])
+
+    # Invalid inputs.
+    invalid_calls = [
+        lambda: hasher.encoded_digest("base65"),
+        lambda: hasher.encoded_digest("z85", 3),
+        lambda: hasher.encoded_digest("base64", uppercase=True),
+        lambda: hasher.encoded_digest("base58", sep=":"),
+        lambda: decode("abc", "hex"),
+        lambda: decode("0OIl", "base58"),
+        lambda: decode("Hello", "base64"),
+        lambda: decode("foo+", "base64url"),
+        lambda: decode("Hell", "z85"),
+        lambda: decode("abcd", "base58", sep=":"),
+    ]
+    for call in invalid_calls:
+        try:
+            call()
+            assert False, "expected a ValueError"
+        except ValueError:
+            pass
+
+    # Huge lengths raise instead of crashing.
+    for huge_length in [2**61, (sys.maxsize // 2) + 1]:
+        for encoding in ["hex", "base32", "base64", "z85"]:
+            try:
+                hasher.encoded_digest(encoding, huge_length)
+                assert False, "should throw"
+            except (OverflowError, MemoryError):
+                pass
+
+    # Base58 takes quadratic time, so it has a maximum length.
+    long_digest = hasher.digest(1024)
+    assert decode(hasher.encoded_digest("base58", 1024), "base58") == long_digest
+    for call in [
+        lambda: hasher.encoded_digest("base58", 1025),
+        lambda: encode(bytes(1025), "base58"),
+        lambda: decode("2" * 2049, "base58"),
+    ]:
+        try:
+            call()
+            assert False, "expected a ValueError"
+        except ValueError:
+            pass
+
+
+@rust_only
+def test_multihash_and_cid() -> None:
+    hasher = blake3(b"foo")
+    digest = hasher.digest()
+    multihash = hasher.multihash()
+    assert multihash == b"\x1e\x20" + digest
+    assert blake3_module.parse_multihash(multihash) == digest
+    assert blake3_module.parse_multihash(bytearray(multihash)) == digest
+    # Long XOF digests need a multi-byte length varint.
+    long_digest = hasher.digest(200, seek=3)
+    long_multihash = hasher.multihash(200, seek=3)
+    assert long_multihash == b"\x1e\xc8\x01" + long_digest
+    assert blake3_module.parse_multihash(long_multihash) == long_digest
+
+    # Raw BLAKE3 CIDs in base32 always start with "bafkr4i".
+    cid = hasher.cid()
+    assert cid.startswith("bafkr4i")
+    assert cid == hasher.cid("raw", "base32") == hasher.cid(0x55)
+    assert blake3_module.parse_cid(cid) == digest
+    for base in ["base16", "base16upper", "base32upper", "base58btc", "base64url"]:
+        for codec in ["dag-cbor", 0x70, 2**63 - 1]:
+            other_cid = hasher.cid(codec, base, length=64)
+            assert blake3_module.parse_cid(other_cid) == hasher.digest(64)
+    assert hasher.cid(base="base16") == "f01551e20" + digest.hex()
+    assert hasher.cid("dag-cbor", "base16upper").startswith("F01711E20")
+
+    bad_multihashes = [
+        b"",
+        b"\x1e",
+        b"\x1e\x20" + digest[:-1],
+        b"\x1e\x20" + digest + b"\0",
+        b"\x1e\x00",
+        b"\x12\x20" + digest,  # SHA2-256
+        b"\x9e\x00\x20" + digest,  # non-minimal varint
+    ]
+    for bad_multihash in bad_multihashes:
+        try:
+            blake3_module.parse_multihash(bad_multihash)
+            assert False, "expected a ValueError"
+        except ValueError:
+            pass
+    bad_cids = [
+        "",
+        "QmYwAPJzv5CZsnA625s3Xf2nemtYgPpHdWEz79ojWnPbdG",  # CIDv0
+        "f02551e20" + digest.hex(),  # CIDv2
+        "f01551220" + digest.hex(),  # SHA2-256
+        "x" + cid[1:],
+        cid[:-1],
+    ]
+    for bad_cid in bad_cids:
+        try:
+            blake3_module.parse_cid(bad_cid)
+            assert False, "expected a ValueError"
+        except ValueError:
+            pass
+    for bad_args in [{"codec": "foo"}, {"codec": -1}, {"base": "base2"}, {"length": 0}]:
+        try:
+            hasher.cid(**bad_args)  # type: ignore
+            assert False, "expected a ValueError"
+        except (ValueError, OverflowError):
+            pass
+
+
+@rust_only
+def test_one_shot_functions() -> None:
+    key = VECTORS["key"].encode()
+    context = "BLAKE3 2019-12-27 16:29:52 test vectors context"
+    for case in VECTORS["cases"]:
+        input_bytes = make_input(int(case["input_len"]))
+        extended_len = len(case["hash"]) // 2
+        for max_threads in [1, 2, blake3_module.AUTO]:
+            assert blake3_module.hash(
+                input_bytes, max_threads=max_threads
+            ) == unhexlify(case["hash"][:64])
+            assert blake3_module.hash(
+                input_bytes, extended_len, max_threads=max_threads
+            ) == unhexlify(case["hash"])
+            assert blake3_module.keyed_hash(
+                key, input_bytes, max_threads=max_threads
+            ) == unhexlify(case["keyed_hash"][:64])
+            assert blake3_module.keyed_hash(
+                key, input_bytes, extended_len, max_threads=max_threads
+            ) == unhexlify(case["keyed_hash"])
+            assert blake3_module.derive_key(
+                context, input_bytes, max_threads=max_threads
+            ) == unhexlify(case["derive_key"][:64])
+            assert blake3_module.derive_key(
+                context, input_bytes, extended_len, max_threads=max_threads
+            ) == unhexlify(case["derive_key"])
+
+    # Large inputs and outputs, which release the GIL and use threads.
+    large_input = make_input(1_000_000)
+    expected = blake3(large_input).digest(1_000_000)
+    assert blake3_module.hash(large_input, 1_000_000, max_threads=4) == expected
+    assert blake3_module.hash(memoryview(large_input)) == expected[:32]
+    assert blake3_module.hash(b"") == blake3().digest()
+
+    bad_calls = [
+        lambda: blake3_module.keyed_hash(b"\0" * 31, b"foo"),
+        lambda: blake3_module.hash(b"foo", max_threads=0),
+        lambda: blake3_module.derive_key(context, b"foo", max_threads=-2),
+    ]
+    for bad_call in bad_calls:
+        try:
+            bad_call()
+            assert False, "expected a ValueError"
+        except ValueError:
+            pass
+    try:
+        blake3_module.hash("foo")  # type: ignore
+        assert False, "expected a TypeError"
+    except TypeError:
+        pass
+
+
+@rust_only
+def test_hash_many() -> None:
+    inputs = [make_input(n) for n in [0, 1, 1023, 1024, 1025, 100_000]] * 3
+    key = bytes(range(32))
+    context = "blake3-py hash_many test context"
+    for max_threads in [1, 2, blake3.AUTO]:
+        assert blake3_module.hash_many(inputs, max_threads=max_threads) == [
+            blake3(x).digest() for x in inputs
+        ]
+        assert blake3_module.hash_many(
+            inputs, key=key, length=100, max_threads=max_threads
+        ) == [blake3(x, key=key).digest(100) for x in inputs]
+        assert blake3_module.hash_many(
+            iter(inputs), derive_key_context=context, max_threads=max_threads
+        ) == [blake3(x, derive_key_context=context).digest() for x in inputs]
+        assert blake3_module.hash_many(
+            inputs, length=7, max_threads=max_threads, concatenate=True
+        ) == b"".join(blake3(x).digest(7) for x in inputs)
+
+    # Other buffer types and edge cases.
+    foos = [b"foo", bytearray(b"foo"), memoryview(b"foo")]
+    assert blake3_module.hash_many(foos) == [blake3(b"foo").digest()] * 3
+    assert blake3_module.hash_many([]) == []
+    assert blake3_module.hash_many([], concatenate=True) == b""
+    assert blake3_module.hash_many([b"foo", b"bar"], length=0) == [b"", b""]
+
+    try:
+        blake3_module.hash_many([b"foo", "bar"])  # type: ignore
+        assert False, "expected a TypeError"
+    except TypeError:
+        pass
+    try:
+        blake3_module.hash_many([b"foo"], key=key, derive_key_context=context)
+        assert False, "expected a ValueError"
+    except ValueError:
+        pass
+
+
+@rust_only
+def test_hash_rows() -> None:
+    key = bytes(range(32))
+    context = "blake3-py hash_rows test context"
+    # Cover rows that are partial blocks, whole blocks, one whole chunk, and
+    # more than a chunk, and enough rows to fill more than one SIMD batch.
+    for row_length in [1, 63, 64, 65, 1000, 1024, 1025]:
+        rows = [make_input(row_length + i)[i:] for i in range(100)]
+        data = b"".join(rows)
+        for max_threads in [1, 2, blake3.AUTO]:
+            assert blake3_module.hash_rows(
+                data, row_length=row_length, max_threads=max_threads
+            ) == b"".join(blake3(row).digest() for row in rows)
+        for length in [0, 8, 32, 100]:
+            assert blake3_module.hash_rows(
+                data, row_length=row_length, key=key, length=length
+            ) == b"".join(blake3(row, key=key).digest(length) for row in rows)
+        assert blake3_module.hash_rows(
+            data, row_length=row_length, derive_key_context=context
+        ) == b"".join(blake3(row, derive_key_context=context).digest() for row in rows)
+        assert blake3_module.hash_rows(
+            data, row_length=row_length, length=8, as_int=True
+        ) == [int.from_bytes(blake3(row).digest(8), "big") for row in rows]
+
+    # 2-D buffers don't need row_length.
+    matrix = memoryview(bytes(range(12))).cast("B", (3, 4))
+    expected = [blake3(bytes(range(i, i + 4))).digest(33) for i in range(0, 12, 4)]
+    assert blake3_module.hash_rows(matrix, length=33) == b"".join(expected)
+    assert blake3_module.hash_rows(matrix, row_length=4, length=33, as_int=True) == [
+        int.from_bytes(x, "big") for x in expected
+    ]
+    assert blake3_module.hash_rows(b"", row_length=10) == b""
+
+    for kwargs in [{}, {"row_length": 0}, {"row_length": 7}]:
+        try:
+            blake3_module.hash_rows(b"foobar", **kwargs)  # type: ignore
+            assert False, "expected a ValueError"
+        except ValueError:
+            pass
+    try:
+        blake3_module.hash_rows(matrix, row_length=3)
+        assert False, "expected a ValueError"
+    except ValueError:
+        pass
+
+
+@rust_only
+def test_hash_rows_numpy() -> None:
+    array = numpy.arange(200 * 40, dtype=numpy.uint8).reshape(200, 40)
+    hashes = blake3_module.hash_rows(array, length=16)
+    assert numpy.frombuffer(hashes, numpy.uint8).reshape(200, 16).tolist() == [
+        list(blake3(row.tobytes()).digest(16)) for row in array
+    ]
+    empty_rows = numpy.zeros((5, 0), numpy.uint8)
+    assert blake3_module.hash_rows(empty_rows) == blake3(b"").digest() * 5
+    try:
+        blake3_module.hash_rows(array[:, ::2])
+        assert False, "expected a BufferError"
+    except BufferError:
+        pass